@@ -0,0 +1,109 @@
+use crate::exporter::{export_channel, ArchiveCompression};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::{path::PathBuf, sync::Arc};
+use tracing::{error, info};
+
+/// Tracks the background jobs exporting channel archives
+#[derive(Clone, Default)]
+pub struct ExportJobs {
+    jobs: Arc<DashMap<String, ExportJob>>,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportJob {
+    pub channel_id: String,
+    pub compression: ArchiveCompression,
+    pub status: ExportStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub days_exported: u64,
+    pub messages_exported: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStatus {
+    Running,
+    Finished,
+    Failed,
+}
+
+impl ExportJobs {
+    /// Starts exporting the channel in the background, returns `false` if an export of it is already running
+    pub fn start(
+        &self,
+        db: Arc<clickhouse::Client>,
+        root_path: PathBuf,
+        channel_id: String,
+        excluded_user_ids: Vec<String>,
+        compression: ArchiveCompression,
+    ) -> bool {
+        if self
+            .jobs
+            .get(&channel_id)
+            .is_some_and(|job| job.status == ExportStatus::Running)
+        {
+            return false;
+        }
+
+        self.jobs.insert(
+            channel_id.clone(),
+            ExportJob {
+                channel_id: channel_id.clone(),
+                compression,
+                status: ExportStatus::Running,
+                started_at: Utc::now(),
+                finished_at: None,
+                days_exported: 0,
+                messages_exported: 0,
+                error: None,
+            },
+        );
+
+        let jobs = self.jobs.clone();
+        tokio::spawn(async move {
+            let result = export_channel(
+                &db,
+                &channel_id,
+                &excluded_user_ids,
+                &root_path,
+                compression,
+                |_, count| {
+                    if let Some(mut job) = jobs.get_mut(&channel_id) {
+                        job.days_exported += 1;
+                        job.messages_exported += count;
+                    }
+                },
+            )
+            .await;
+
+            if let Some(mut job) = jobs.get_mut(&channel_id) {
+                job.finished_at = Some(Utc::now());
+                match result {
+                    Ok(()) => {
+                        info!("Exported archive of channel {channel_id}");
+                        job.status = ExportStatus::Finished;
+                    }
+                    Err(err) => {
+                        error!("Could not export archive of channel {channel_id}: {err:#}");
+                        job.status = ExportStatus::Failed;
+                        job.error = Some(format!("{err:#}"));
+                    }
+                }
+            }
+        });
+
+        true
+    }
+
+    pub fn list(&self) -> Vec<ExportJob> {
+        let mut jobs: Vec<ExportJob> = self.jobs.iter().map(|job| job.clone()).collect();
+        jobs.sort_by_key(|job| job.started_at);
+        jobs
+    }
+}
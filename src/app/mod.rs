@@ -1,15 +1,16 @@
 pub mod cache;
+pub mod export;
+pub mod purge;
+pub mod retention;
 
-use self::cache::UsersCache;
+use self::{cache::UsersCache, export::ExportJobs};
 use crate::{
-    config::Config,
-    db::{delete_user_logs, writer::FlushBuffer},
-    error::Error,
-    Result,
+    archive::ColdArchive, config::Config, db::writer::FlushBuffer, error::Error,
+    logs::live::LiveMessage, Result,
 };
-use anyhow::Context;
 use dashmap::DashSet;
 use std::{collections::HashMap, sync::Arc};
+use tokio::sync::broadcast;
 use tracing::{debug, info};
 use twitch_api::{helix::users::GetUsersRequest, twitch_oauth2::AppAccessToken, HelixClient};
 
@@ -22,6 +23,9 @@ pub struct App {
     pub db: Arc<clickhouse::Client>,
     pub config: Arc<Config>,
     pub flush_buffer: FlushBuffer,
+    pub live_messages: broadcast::Sender<LiveMessage>,
+    pub export_jobs: ExportJobs,
+    pub cold_archive: Option<Arc<ColdArchive>>,
 }
 
 impl App {
@@ -125,15 +129,14 @@ impl App {
         }
     }
 
+    /// Hides the user's logs immediately, while the actual deletion runs in the background
     pub async fn optout_user(&self, user_id: &str) -> anyhow::Result<()> {
-        delete_user_logs(&self.db, user_id)
-            .await
-            .context("Could not delete logs")?;
-
         self.config.opt_out.insert(user_id.to_owned(), true);
         self.config.save()?;
         info!("User {user_id} opted out");
 
+        purge::start(&self.db, user_id).await?;
+
         Ok(())
     }
 
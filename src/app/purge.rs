@@ -0,0 +1,195 @@
+use crate::{
+    archive::ColdArchive,
+    db::{delete_user_logs, get_user_logs_deletion_status, USER_LOGS_TABLES},
+    ShutdownRx,
+};
+use chrono::{DateTime, Utc};
+use clickhouse::Row;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{error, info, warn};
+
+pub const PURGES_TABLE: &str = "__rustlog_user_purges";
+
+const PURGE_CHECK_INTERVAL_SECONDS: u64 = 60;
+
+/// Deletion of an opted out user's logs, stored in the database so it survives restarts
+#[derive(Row, Serialize, Deserialize)]
+struct PurgeRow {
+    user_id: String,
+    started_at: u32,
+    finished_at: Option<u32>,
+    /// Version of the row, in milliseconds
+    updated_at: u64,
+}
+
+impl PurgeRow {
+    fn new(user_id: &str, started_at: u32, finished_at: Option<u32>) -> Self {
+        Self {
+            user_id: user_id.to_owned(),
+            started_at,
+            finished_at,
+            updated_at: Utc::now().timestamp_millis() as u64,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeJob {
+    pub user_id: String,
+    pub status: PurgeStatus,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// How many data parts still need to be rewritten by the database
+    pub parts_remaining: Option<u64>,
+    /// Why the last attempt of a mutation failed, the database keeps retrying it
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PurgeStatus {
+    Running,
+    Finished,
+    Failed,
+}
+
+/// Records the purge and submits the deletion mutations, without waiting for them to complete
+pub async fn start(db: &clickhouse::Client, user_id: &str) -> anyhow::Result<()> {
+    let mut insert = db.insert(PURGES_TABLE)?;
+    insert
+        .write(&PurgeRow::new(user_id, Utc::now().timestamp() as u32, None))
+        .await?;
+    insert.end().await?;
+
+    // Retried by `run` when submitting fails
+    if let Err(err) = delete_user_logs(db, user_id).await {
+        error!("Could not submit deletion of user {user_id}: {err}");
+    }
+
+    Ok(())
+}
+
+/// Lists all purges with the progress of the running ones
+pub async fn list(db: &clickhouse::Client) -> anyhow::Result<Vec<PurgeJob>> {
+    let rows = read_purges(db, false).await?;
+    let mut jobs = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let mut job = PurgeJob {
+            user_id: row.user_id,
+            status: PurgeStatus::Finished,
+            started_at: DateTime::from_timestamp(row.started_at.into(), 0).unwrap_or_default(),
+            finished_at: row
+                .finished_at
+                .and_then(|finished_at| DateTime::from_timestamp(finished_at.into(), 0)),
+            parts_remaining: Some(0),
+            error: None,
+        };
+
+        if job.finished_at.is_none() {
+            let status = get_user_logs_deletion_status(db, &job.user_id).await?;
+            job.parts_remaining = Some(status.parts_to_do);
+            job.status = if status.fail_reason.is_empty() {
+                PurgeStatus::Running
+            } else {
+                job.error = Some(status.fail_reason);
+                PurgeStatus::Failed
+            };
+        }
+
+        jobs.push(job);
+    }
+
+    jobs.sort_by_key(|job| job.started_at);
+    Ok(jobs)
+}
+
+/// Periodically marks completed purges as finished after removing the user from the archive,
+/// and resubmits the ones whose mutations are gone, which happens when submitting failed or the mutations were killed
+pub async fn run(
+    db: Arc<clickhouse::Client>,
+    cold_archive: Option<Arc<ColdArchive>>,
+    mut shutdown_rx: ShutdownRx,
+) {
+    let mut interval = interval(std::time::Duration::from_secs(PURGE_CHECK_INTERVAL_SECONDS));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                if let Err(err) = check_running(&db, cold_archive.as_deref()).await {
+                    error!("Could not check running purges: {err:#}");
+                }
+            }
+            Ok(()) = shutdown_rx.changed() => break,
+        }
+    }
+}
+
+async fn check_running(
+    db: &clickhouse::Client,
+    cold_archive: Option<&ColdArchive>,
+) -> anyhow::Result<()> {
+    for row in read_purges(db, true).await? {
+        if let Err(err) = check_purge(db, cold_archive, &row).await {
+            error!("Could not check purge of user {}: {err:#}", row.user_id);
+        }
+    }
+
+    Ok(())
+}
+
+async fn check_purge(
+    db: &clickhouse::Client,
+    cold_archive: Option<&ColdArchive>,
+    row: &PurgeRow,
+) -> anyhow::Result<()> {
+    let status = get_user_logs_deletion_status(db, &row.user_id).await?;
+
+    if status.mutation_count < USER_LOGS_TABLES.len() as u64 {
+        info!("Resubmitting deletion of user {}", row.user_id);
+        delete_user_logs(db, &row.user_id).await?;
+    } else if status.is_done {
+        if let Some(cold_archive) = cold_archive {
+            let count = cold_archive.delete_user_logs(db, &row.user_id).await?;
+            info!("Deleted {count} archived messages of user {}", row.user_id);
+        }
+        info!("Deleted logs of user {}", row.user_id);
+
+        let mut insert = db.insert(PURGES_TABLE)?;
+        insert
+            .write(&PurgeRow::new(
+                &row.user_id,
+                row.started_at,
+                Some(Utc::now().timestamp() as u32),
+            ))
+            .await?;
+        insert.end().await?;
+    } else if !status.fail_reason.is_empty() {
+        warn!(
+            "Deletion of user {} is failing: {}",
+            row.user_id, status.fail_reason
+        );
+    }
+
+    Ok(())
+}
+
+async fn read_purges(db: &clickhouse::Client, running_only: bool) -> anyhow::Result<Vec<PurgeRow>> {
+    let condition = if running_only {
+        " WHERE finished_at IS NULL"
+    } else {
+        ""
+    };
+    let rows = db
+        .query(&format!(
+            "SELECT ?fields FROM {PURGES_TABLE} FINAL{condition}"
+        ))
+        .fetch_all::<PurgeRow>()
+        .await?;
+    Ok(rows)
+}
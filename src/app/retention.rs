@@ -0,0 +1,54 @@
+use super::App;
+use crate::{db::delete_channel_logs_before, ShutdownRx};
+use chrono::{Duration, Utc};
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{debug, error, info};
+
+const RETENTION_INTERVAL_SECONDS: u64 = 60 * 60;
+
+/// Periodically deletes the logs which are older than the retention period of their channel
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let mut interval = interval(std::time::Duration::from_secs(RETENTION_INTERVAL_SECONDS));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => apply_retention(&app).await,
+            Ok(()) = shutdown_rx.changed() => break,
+        }
+    }
+}
+
+async fn apply_retention(app: &App) {
+    let policies: Vec<(String, u32)> = app
+        .config
+        .channel_retention
+        .iter()
+        .map(|entry| (entry.key().clone(), *entry.value()))
+        .collect();
+
+    for (channel_id, days) in policies {
+        let cutoff = Utc::now() - Duration::days(days.into());
+
+        match delete_channel_logs_before(&app.db, &channel_id, cutoff).await {
+            Ok(false) => debug!("No expired logs in channel {channel_id}"),
+            Ok(true) => info!("Deleted logs of channel {channel_id} older than {days} days"),
+            Err(err) => error!("Could not delete expired logs of channel {channel_id}: {err}"),
+        }
+
+        if let Some(cold_archive) = &app.cold_archive {
+            match cold_archive
+                .delete_channel_logs_before(&app.db, &channel_id, cutoff)
+                .await
+            {
+                Ok(0) => (),
+                Ok(count) => info!(
+                    "Deleted {count} archived days of channel {channel_id} older than {days} days"
+                ),
+                Err(err) => error!(
+                    "Could not delete expired archived logs of channel {channel_id}: {err:#}"
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,652 @@
+use crate::{
+    db::{read_channel_log_days, schema::StructuredMessage},
+    exporter::{export_channel_days, ArchiveCompression, ArchiveWriter},
+    logs::deletions::MessageDeletions,
+    migrator::{
+        get_day_path, open_day_file, parse_line,
+        reader::{
+            LogsReader, COMPRESSED_CHANNEL_FILE, UNCOMPRESSED_CHANNEL_FILE, ZSTD_CHANNEL_FILE,
+        },
+        LineOutcome,
+    },
+    web::schema::{DeletedFilter, LogsParams},
+};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Datelike, Days, Duration, Months, NaiveTime, TimeZone, Utc};
+use dashmap::DashMap;
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::BufRead,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tracing::{debug, info};
+
+pub const ARCHIVED_PARTITIONS_TABLE: &str = "__rustlog_archived_partitions";
+/// Days of every channel each user has archived messages in, so they can be found without reading the whole archive
+pub const ARCHIVED_USER_DAYS_TABLE: &str = "__rustlog_archived_user_days";
+
+const ARCHIVED_MONTHS_EXPIRY_SECONDS: u64 = 300;
+
+/// Moves the monthly partitions of `message_structured` older than `older_than_months` into a justlog directory tree,
+/// and drops them from the database once all of their messages were written.
+/// Messages of `excluded_user_ids` are not archived, so they are gone once the partition is dropped
+pub async fn archive_partitions(
+    db: &clickhouse::Client,
+    root_path: &Path,
+    older_than_months: u32,
+    excluded_user_ids: &[String],
+    compression: ArchiveCompression,
+) -> anyhow::Result<()> {
+    let cutoff = Utc::now()
+        .checked_sub_months(Months::new(older_than_months))
+        .context("Invalid number of months")?;
+    let cutoff_month = month_number(cutoff);
+
+    let partitions: Vec<u32> = db
+        .query("SELECT DISTINCT toUInt32(partition) AS month FROM system.parts WHERE database = currentDatabase() AND table = 'message_structured' AND active ORDER BY month")
+        .fetch_all::<u32>()
+        .await
+        .context("Could not fetch partition list")?
+        .into_iter()
+        .filter(|month| *month < cutoff_month)
+        .collect();
+    info!("Archiving {} partitions", partitions.len());
+
+    for month in partitions {
+        archive_partition(db, root_path, month, excluded_user_ids, compression)
+            .await
+            .with_context(|| format!("Could not archive partition {month}"))?;
+    }
+
+    Ok(())
+}
+
+async fn archive_partition(
+    db: &clickhouse::Client,
+    root_path: &Path,
+    month: u32,
+    excluded_user_ids: &[String],
+    compression: ArchiveCompression,
+) -> anyhow::Result<()> {
+    let mut count_query =
+        "SELECT count() FROM message_structured WHERE toYYYYMM(timestamp) = ?".to_owned();
+    if !excluded_user_ids.is_empty() {
+        count_query.push_str(" AND user_id NOT IN ?");
+    }
+    let mut count_query = db.query(&count_query).bind(month);
+    if !excluded_user_ids.is_empty() {
+        count_query = count_query.bind(excluded_user_ids);
+    }
+    let total_count = count_query.fetch_one::<u64>().await?;
+    let channel_ids = db
+        .query("SELECT DISTINCT channel_id FROM message_structured WHERE toYYYYMM(timestamp) = ?")
+        .bind(month)
+        .fetch_all::<String>()
+        .await?;
+    info!(
+        "Archiving {total_count} messages from {} channels in partition {month}",
+        channel_ids.len()
+    );
+
+    let mut exported_count = 0;
+    for channel_id in channel_ids {
+        let days: Vec<DateTime<Utc>> = read_channel_log_days(db, &channel_id)
+            .await?
+            .into_iter()
+            .filter(|day| month_number(*day) == month)
+            .collect();
+
+        export_channel_days(
+            db,
+            &channel_id,
+            excluded_user_ids,
+            &days,
+            root_path,
+            compression,
+            |_, count| {
+                exported_count += count;
+            },
+        )
+        .await?;
+        debug!("Archived channel {channel_id} in partition {month}");
+    }
+
+    // Messages written to the partition in the meantime would be lost when dropping it
+    if exported_count != total_count {
+        bail!("Exported {exported_count} out of {total_count} messages, the partition was modified during the export");
+    }
+
+    let mut index_query = format!("INSERT INTO {ARCHIVED_USER_DAYS_TABLE} SELECT DISTINCT user_id, channel_id, toDate(timestamp, 'UTC') FROM message_structured WHERE toYYYYMM(timestamp) = ?");
+    if !excluded_user_ids.is_empty() {
+        index_query.push_str(" AND user_id NOT IN ?");
+    }
+    let mut index_query = db.query(&index_query).bind(month);
+    if !excluded_user_ids.is_empty() {
+        index_query = index_query.bind(excluded_user_ids);
+    }
+    index_query
+        .execute()
+        .await
+        .context("Could not index the archived days of users")?;
+
+    db.query(&format!(
+        "INSERT INTO {ARCHIVED_PARTITIONS_TABLE} VALUES (?, now())"
+    ))
+    .bind(month)
+    .execute()
+    .await?;
+    db.query("ALTER TABLE message_structured DROP PARTITION ?")
+        .bind(month)
+        .execute()
+        .await?;
+
+    info!("Archived partition {month}");
+
+    Ok(())
+}
+
+/// Months in the `toYYYYMM` format which were moved to the archive
+pub async fn read_archived_months(db: &clickhouse::Client) -> anyhow::Result<BTreeSet<u32>> {
+    let months = db
+        .query(&format!(
+            "SELECT DISTINCT month FROM {ARCHIVED_PARTITIONS_TABLE}"
+        ))
+        .fetch_all::<u32>()
+        .await
+        .context("Could not read archived partitions")?;
+    Ok(months.into_iter().collect())
+}
+
+/// Months in the `toYYYYMM` format touched by the range
+pub fn months_in_range((from, to): (DateTime<Utc>, DateTime<Utc>)) -> Vec<u32> {
+    let mut months = Vec::new();
+    if from >= to {
+        return months;
+    }
+    let last = to - Duration::milliseconds(1);
+
+    let mut current = from
+        .with_day(1)
+        .expect("First day of the month is always valid")
+        .with_time(NaiveTime::MIN)
+        .unwrap();
+    while current <= last {
+        months.push(month_number(current));
+        current = current + Months::new(1);
+    }
+
+    months
+}
+
+/// The archive folder the bot reads old channel logs from
+pub struct ColdArchive {
+    root_path: PathBuf,
+    /// The months are changed by the separate `archive` command, so they are only cached for a while
+    archived_months: Mutex<Option<(Instant, Arc<BTreeSet<u32>>)>>,
+}
+
+impl ColdArchive {
+    pub fn new(root_path: PathBuf) -> Self {
+        Self {
+            root_path,
+            archived_months: Mutex::default(),
+        }
+    }
+
+    /// Whether every month touched by the range was moved to the archive
+    pub async fn covers(
+        &self,
+        db: &clickhouse::Client,
+        range: (DateTime<Utc>, DateTime<Utc>),
+    ) -> anyhow::Result<bool> {
+        let months = months_in_range(range);
+        if months.is_empty() {
+            return Ok(false);
+        }
+
+        let archived_months = self.archived_months(db).await?;
+        Ok(months.iter().all(|month| archived_months.contains(month)))
+    }
+
+    async fn archived_months(&self, db: &clickhouse::Client) -> anyhow::Result<Arc<BTreeSet<u32>>> {
+        if let Some((fetched_at, months)) = &*self.archived_months.lock().unwrap() {
+            if fetched_at.elapsed().as_secs() < ARCHIVED_MONTHS_EXPIRY_SECONDS {
+                return Ok(months.clone());
+            }
+        }
+
+        let months = Arc::new(read_archived_months(db).await?);
+        *self.archived_months.lock().unwrap() = Some((Instant::now(), months.clone()));
+        Ok(months)
+    }
+
+    /// Reads the channel's messages in the range one day at a time, in the order and with the pagination of the params.
+    /// Stops once `limit` messages were read, and leaves out the messages of opted out users
+    pub async fn read_channel(
+        &self,
+        channel_id: &str,
+        params: LogsParams,
+        (from, to): (DateTime<Utc>, DateTime<Utc>),
+        opt_out: &DashMap<String, bool>,
+    ) -> anyhow::Result<Vec<StructuredMessage<'static>>> {
+        let mut days = Vec::new();
+        let mut day = from.with_time(NaiveTime::MIN).unwrap();
+        while day < to {
+            days.push(day);
+            day = day + Days::new(1);
+        }
+
+        self.read_days(channel_id, days, params, (from, to), |msg| {
+            !opt_out.contains_key(msg.user_id.as_ref())
+        })
+        .await
+    }
+
+    /// Reads the user's messages in the range which `filter` accepts, like `read_channel`,
+    /// but only from the days the user has messages in
+    pub async fn read_user(
+        &self,
+        db: &clickhouse::Client,
+        channel_id: &str,
+        user_id: &str,
+        params: LogsParams,
+        (from, to): (DateTime<Utc>, DateTime<Utc>),
+        filter: impl Fn(&StructuredMessage) -> bool,
+    ) -> anyhow::Result<Vec<StructuredMessage<'static>>> {
+        let days = self
+            .user_days(db, channel_id, user_id)
+            .await?
+            .into_iter()
+            .filter(|day| *day + Days::new(1) > from && *day < to)
+            .collect();
+
+        self.read_days(channel_id, days, params, (from, to), |msg| {
+            msg.user_id == user_id && filter(msg)
+        })
+        .await
+    }
+
+    /// Reads the given days in chronological order
+    async fn read_days(
+        &self,
+        channel_id: &str,
+        mut days: Vec<DateTime<Utc>>,
+        params: LogsParams,
+        (from, to): (DateTime<Utc>, DateTime<Utc>),
+        keep: impl Fn(&StructuredMessage) -> bool,
+    ) -> anyhow::Result<Vec<StructuredMessage<'static>>> {
+        let timestamp_range = (from.timestamp_millis() as u64)..(to.timestamp_millis() as u64);
+        let limit = params.limit.map(|limit| limit as usize);
+        let mut to_skip = params.offset.unwrap_or(0) as usize;
+
+        if params.reverse {
+            days.reverse();
+        }
+
+        let mut messages = Vec::new();
+
+        for day in days {
+            let mut day_messages = self.read_day(channel_id, day).await?;
+
+            if let Some(filter) = params.deleted {
+                // Messages from the end of the day can be deleted on the next one
+                let next_day_messages = self.read_day(channel_id, day + Days::new(1)).await?;
+
+                let mut deletions = MessageDeletions::default();
+                deletions.extend_from_messages(&day_messages);
+                deletions.extend_from_messages(&next_day_messages);
+                day_messages
+                    .retain(|msg| deletions.contains(msg) == (filter == DeletedFilter::Only));
+            }
+
+            day_messages.retain(|msg| {
+                timestamp_range.contains(&msg.timestamp)
+                    && keep(msg)
+                    && params
+                        .cursor
+                        .is_none_or(|cursor| cursor.is_followed_by(msg, params.reverse))
+            });
+            if params.reverse {
+                day_messages.reverse();
+            }
+
+            for msg in day_messages {
+                if to_skip > 0 {
+                    to_skip -= 1;
+                    continue;
+                }
+
+                messages.push(msg);
+                if limit.is_some_and(|limit| messages.len() >= limit) {
+                    return Ok(messages);
+                }
+            }
+        }
+
+        Ok(messages)
+    }
+
+    async fn read_day(
+        &self,
+        channel_id: &str,
+        day: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<StructuredMessage<'static>>> {
+        let root_path = self.root_path.clone();
+        let channel_id = channel_id.to_owned();
+        tokio::task::spawn_blocking(move || read_archived_day(&root_path, &channel_id, day)).await?
+    }
+
+    /// Start of every archived day of the channel, oldest first
+    pub async fn channel_days(&self, channel_id: &str) -> anyhow::Result<Vec<DateTime<Utc>>> {
+        let reader = LogsReader {
+            root_path: Arc::new(self.root_path.clone()),
+        };
+        let channel_id = channel_id.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            if !reader.root_path.join(&channel_id).exists() {
+                return Ok(Vec::new());
+            }
+            archived_days(&reader, &channel_id)
+        })
+        .await?
+    }
+
+    /// Start of every archived day the user has messages in the channel on, oldest first
+    pub async fn user_days(
+        &self,
+        db: &clickhouse::Client,
+        channel_id: &str,
+        user_id: &str,
+    ) -> anyhow::Result<Vec<DateTime<Utc>>> {
+        let timestamps = db
+            .query(&format!("SELECT DISTINCT toUInt32(toDateTime(day, 'UTC')) AS date FROM {ARCHIVED_USER_DAYS_TABLE} WHERE user_id = ? AND channel_id = ? ORDER BY date"))
+            .bind(user_id)
+            .bind(channel_id)
+            .fetch_all::<u32>()
+            .await
+            .context("Could not read archived days of user")?;
+
+        Ok(timestamps
+            .into_iter()
+            .filter_map(|timestamp| DateTime::from_timestamp(timestamp.into(), 0))
+            .collect())
+    }
+
+    /// Removes the user's messages from the archived days they have messages in, returning how many were removed
+    pub async fn delete_user_logs(
+        &self,
+        db: &clickhouse::Client,
+        user_id: &str,
+    ) -> anyhow::Result<u64> {
+        let days = db
+            .query(&format!("SELECT DISTINCT channel_id, toUInt32(toDateTime(day, 'UTC')) FROM {ARCHIVED_USER_DAYS_TABLE} WHERE user_id = ?"))
+            .bind(user_id)
+            .fetch_all::<(String, u32)>()
+            .await
+            .context("Could not read archived days of user")?;
+
+        let root_path = self.root_path.clone();
+        let owned_user_id = user_id.to_owned();
+        let removed_count = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+            let mut removed_count = 0;
+            for (channel_id, timestamp) in days {
+                let day = DateTime::from_timestamp(timestamp.into(), 0).context("Invalid day")?;
+                removed_count += rewrite_archived_day(&root_path, &channel_id, day, |msg| {
+                    msg.user_id != owned_user_id
+                })
+                .with_context(|| format!("Could not rewrite channel {channel_id} date {day}"))?;
+            }
+            Ok(removed_count)
+        })
+        .await??;
+
+        db.query(&format!(
+            "DELETE FROM {ARCHIVED_USER_DAYS_TABLE} WHERE user_id = ?"
+        ))
+        .bind(user_id)
+        .execute()
+        .await?;
+
+        Ok(removed_count)
+    }
+
+    /// Deletes the channel's archived days which ended before the cutoff, returning how many were deleted
+    pub async fn delete_channel_logs_before(
+        &self,
+        db: &clickhouse::Client,
+        channel_id: &str,
+        cutoff: DateTime<Utc>,
+    ) -> anyhow::Result<usize> {
+        let root_path = self.root_path.clone();
+        let owned_channel_id = channel_id.to_owned();
+
+        let deleted_count = tokio::task::spawn_blocking(move || -> anyhow::Result<usize> {
+            let reader = LogsReader {
+                root_path: Arc::new(root_path.clone()),
+            };
+            if !root_path.join(&owned_channel_id).exists() {
+                return Ok(0);
+            }
+
+            let mut deleted_count = 0;
+            for day in archived_days(&reader, &owned_channel_id)? {
+                if day + Days::new(1) <= cutoff {
+                    fs::remove_dir_all(get_day_path(&root_path, &owned_channel_id, day))?;
+                    deleted_count += 1;
+                }
+            }
+
+            Ok(deleted_count)
+        })
+        .await??;
+
+        if deleted_count > 0 {
+            db.query(&format!(
+                "DELETE FROM {ARCHIVED_USER_DAYS_TABLE} WHERE channel_id = ? AND day < ?"
+            ))
+            .bind(channel_id)
+            .bind(cutoff.date_naive().to_string())
+            .execute()
+            .await?;
+        }
+
+        Ok(deleted_count)
+    }
+}
+
+/// Reads the messages of an archived day in chronological order
+fn read_archived_day(
+    root_path: &Path,
+    channel_id: &str,
+    day: DateTime<Utc>,
+) -> anyhow::Result<Vec<StructuredMessage<'static>>> {
+    if !get_day_path(root_path, channel_id, day).exists() {
+        return Ok(Vec::new());
+    }
+
+    let (reader, _) = open_day_file(root_path, channel_id, day)?;
+    let mut messages = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if let LineOutcome::Parsed(msg) =
+            parse_line(channel_id, &line, day, |msg, _| msg.into_owned())
+        {
+            messages.push(msg);
+        }
+    }
+
+    messages.sort_by_key(|msg| (msg.timestamp, msg.uuid()));
+    Ok(messages)
+}
+
+/// Rewrites the day file without the messages that `keep` returns false for, returning how many were removed
+fn rewrite_archived_day(
+    root_path: &Path,
+    channel_id: &str,
+    day: DateTime<Utc>,
+    keep: impl Fn(&StructuredMessage<'_>) -> bool,
+) -> anyhow::Result<u64> {
+    let day_path = get_day_path(root_path, channel_id, day);
+    // Same order as `open_day_file`, plain text files have no compression
+    let Some((file_name, compression)) = [
+        (COMPRESSED_CHANNEL_FILE, Some(ArchiveCompression::Gzip)),
+        (ZSTD_CHANNEL_FILE, Some(ArchiveCompression::Zstd)),
+        (UNCOMPRESSED_CHANNEL_FILE, None),
+    ]
+    .into_iter()
+    .find(|(file_name, _)| day_path.join(file_name).exists()) else {
+        return Ok(0);
+    };
+
+    let (reader, _) = open_day_file(root_path, channel_id, day)?;
+    let mut kept_lines = Vec::new();
+    let mut removed_count = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        // Lines that can't be parsed are kept as they are
+        let keep_line = match parse_line(channel_id, &line, day, |msg, _| keep(&msg)) {
+            LineOutcome::Parsed(keep_line) => keep_line,
+            LineOutcome::UnknownType | LineOutcome::Unparsable => true,
+        };
+
+        if keep_line {
+            kept_lines.push(line);
+        } else {
+            removed_count += 1;
+        }
+    }
+
+    if removed_count > 0 {
+        let file_path = day_path.join(file_name);
+        let tmp_path = file_path.with_extension("tmp");
+
+        let mut writer = match compression {
+            Some(compression) => ArchiveWriter::create(&tmp_path, compression)?,
+            None => ArchiveWriter::create_plain(&tmp_path)?,
+        };
+        for line in kept_lines {
+            writer.write_line(&line)?;
+        }
+        writer.finish()?;
+        fs::rename(&tmp_path, &file_path)?;
+    }
+
+    Ok(removed_count)
+}
+
+fn archived_days(reader: &LogsReader, channel_id: &str) -> anyhow::Result<Vec<DateTime<Utc>>> {
+    let (years, _) = reader.get_available_channel_logs(channel_id)?;
+
+    let mut days = Vec::new();
+    for (year, months) in years {
+        for (month, month_days) in months {
+            for day in month_days {
+                if let Some(date) = Utc
+                    .with_ymd_and_hms(year as i32, month, day, 0, 0, 0)
+                    .single()
+                {
+                    days.push(date);
+                }
+            }
+        }
+    }
+
+    Ok(days)
+}
+
+fn month_number(datetime: DateTime<Utc>) -> u32 {
+    datetime.year() as u32 * 100 + datetime.month()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{months_in_range, read_archived_day, rewrite_archived_day};
+    use crate::{
+        exporter::{ArchiveCompression, ArchiveWriter},
+        migrator::get_day_path,
+    };
+    use chrono::{TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[test]
+    fn range_months() {
+        let from = Utc.with_ymd_and_hms(2023, 11, 15, 12, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap();
+        assert_eq!(vec![202311, 202312, 202401], months_in_range((from, to)));
+
+        let from = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 3, 6, 0, 0, 0).unwrap();
+        assert_eq!(vec![202403], months_in_range((from, to)));
+
+        assert_eq!(Vec::<u32>::new(), months_in_range((from, from)));
+    }
+
+    #[test]
+    fn rewrite_day_without_user() {
+        let root_path = std::env::temp_dir().join(format!("rustlog-archive-{}", Uuid::new_v4()));
+        let day = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        let day_path = get_day_path(&root_path, "1", day);
+        fs::create_dir_all(&day_path).unwrap();
+
+        let mut writer = ArchiveWriter::create(
+            &day_path.join(ArchiveCompression::Gzip.file_name()),
+            ArchiveCompression::Gzip,
+        )
+        .unwrap();
+        for (user_id, ts) in [
+            ("10", 1709600000000u64),
+            ("20", 1709600001000),
+            ("10", 1709600002000),
+        ] {
+            writer
+                .write_line(&format!("@room-id=1;tmi-sent-ts={ts};user-id={user_id} :u{user_id}!u{user_id}@u{user_id}.tmi.twitch.tv PRIVMSG #c :hello"))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+
+        let removed =
+            rewrite_archived_day(&root_path, "1", day, |msg| msg.user_id != "10").unwrap();
+        assert_eq!(2, removed);
+
+        let user_ids: Vec<String> = read_archived_day(&root_path, "1", day)
+            .unwrap()
+            .into_iter()
+            .map(|msg| msg.user_id.into_owned())
+            .collect();
+        assert_eq!(vec!["20".to_owned()], user_ids);
+
+        fs::remove_dir_all(root_path).unwrap();
+    }
+
+    #[test]
+    fn rewrite_plain_day() {
+        let root_path = std::env::temp_dir().join(format!("rustlog-archive-{}", Uuid::new_v4()));
+        let day = Utc.with_ymd_and_hms(2024, 3, 5, 0, 0, 0).unwrap();
+        let day_path = get_day_path(&root_path, "1", day);
+        fs::create_dir_all(&day_path).unwrap();
+
+        let lines: Vec<String> = [("10", 1709600000000u64), ("20", 1709600001000)]
+            .into_iter()
+            .map(|(user_id, ts)| format!("@room-id=1;tmi-sent-ts={ts};user-id={user_id} :u{user_id}!u{user_id}@u{user_id}.tmi.twitch.tv PRIVMSG #c :hello"))
+            .collect();
+        fs::write(day_path.join("channel.txt"), lines.join("\n")).unwrap();
+
+        let removed =
+            rewrite_archived_day(&root_path, "1", day, |msg| msg.user_id != "10").unwrap();
+        assert_eq!(1, removed);
+        assert_eq!(
+            format!("{}\n", lines[1]),
+            fs::read_to_string(day_path.join("channel.txt")).unwrap()
+        );
+
+        fs::remove_dir_all(root_path).unwrap();
+    }
+}
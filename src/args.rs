@@ -1,3 +1,4 @@
+use crate::{exporter::ArchiveCompression, importer::ImportFormat};
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
@@ -20,5 +21,48 @@ pub enum Command {
         /// Parallel migration jobs
         #[clap(short, long, default_value_t = 1)]
         jobs: usize,
+        /// Skip the days completed by previous runs
+        #[clap(long)]
+        resume: bool,
+        /// Parse all files and report the results without inserting anything
+        #[clap(long, conflicts_with_all = ["resume", "verify"])]
+        dry_run: bool,
+        /// Compare the per-day message counts in the database with the source files
+        #[clap(long, conflicts_with = "resume")]
+        verify: bool,
+    },
+    /// Export channel logs into a justlog directory tree, which `migrate` can read
+    Export {
+        /// The folder to write the logs to
+        #[clap(short, long, value_parser)]
+        target_dir: String,
+        /// List of channel ids to export
+        #[clap(short, long, value_parser, required = true)]
+        channel_id: Vec<String>,
+        /// Compression of the daily files
+        #[clap(long, value_enum, default_value_t)]
+        compression: ArchiveCompression,
+    },
+    /// Import logs from raw IRC dumps or rustlog's own `json`/`ndjson` responses
+    Import {
+        /// Files to import, optionally compressed with gzip (`.gz`) or zstd (`.zst`)
+        #[clap(short, long, value_parser, required = true)]
+        file: Vec<String>,
+        #[clap(long, value_enum)]
+        format: ImportFormat,
+        /// Channel id for messages without a `room-id` tag
+        #[clap(short, long, value_parser)]
+        channel_id: Option<String>,
+    },
+    /// Remove duplicate messages from the database. Needs exclusive access: rustlog and the other commands must not be running at the same time
+    Dedupe,
+    /// Move old monthly partitions out of the database into the cold archive folder, where they remain readable
+    Archive {
+        /// Archive the partitions of months that are older than this
+        #[clap(short, long)]
+        months: u32,
+        /// Compression of the daily files
+        #[clap(long, value_enum, default_value_t)]
+        compression: ArchiveCompression,
     },
 }
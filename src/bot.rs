@@ -1,21 +1,24 @@
 use crate::{
     app::App,
+    config::{BotToken, Config},
     db::schema::{StructuredMessage, UnstructuredMessage},
     logs::extract::{extract_channel_and_user_from_raw, extract_raw_timestamp},
     ShutdownRx,
 };
 use anyhow::{anyhow, Context};
-use chrono::Utc;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use prometheus::{register_int_counter_vec, IntCounterVec};
-use std::time::Duration;
+use std::{fmt, sync::Arc, time::Duration};
+use thiserror::Error;
 use tokio::{
     sync::mpsc::{Receiver, Sender},
     time::sleep,
 };
 use tracing::{debug, error, info, log::warn, trace};
 use twitch_irc::{
-    login::LoginCredentials,
+    login::{LoginCredentials, TokenStorage, UserAccessToken},
     message::{AsRawIRC, IRCMessage, ServerMessage},
     ClientConfig, SecureTCPTransport, TwitchIRCClient,
 };
@@ -53,6 +56,10 @@ pub async fn run<C: LoginCredentials>(
     bot.run(login_credentials, shutdown_rx, command_rx).await;
 }
 
+#[derive(Error, Debug)]
+#[error("User {0} is not an admin")]
+struct NotAdmin(String);
+
 #[derive(Clone)]
 struct Bot {
     app: App,
@@ -158,11 +165,27 @@ impl Bot {
         if let ServerMessage::Privmsg(privmsg) = &msg {
             trace!("Processing message {}", privmsg.message_text);
             if let Some(cmd) = privmsg.message_text.strip_prefix(COMMAND_PREFIX) {
-                if let Err(err) = self
+                let reply = match self
                     .handle_command(cmd, client, &privmsg.sender.id, &privmsg.sender.login)
                     .await
                 {
-                    warn!("Could not handle command {cmd}: {err:#}");
+                    Ok(reply) => reply,
+                    // Commands of other users are ignored silently
+                    Err(err) if err.is::<NotAdmin>() => {
+                        debug!("Ignoring command {cmd}: {err}");
+                        None
+                    }
+                    Err(err) => {
+                        warn!("Could not handle command {cmd}: {err:#}");
+                        Some("Could not handle command".to_owned())
+                    }
+                };
+
+                // Anonymous connections cannot send messages
+                if let Some(reply) = reply.filter(|_| self.app.config.bot_login.is_some()) {
+                    if let Err(err) = client.say_in_reply_to(privmsg, reply).await {
+                        error!("Could not reply to command: {err}");
+                    }
                 }
             }
         }
@@ -182,7 +205,7 @@ impl Bot {
         {
             Ok(())
         } else {
-            Err(anyhow!("User {user_login} is not an admin"))
+            Err(NotAdmin(user_login.to_owned()).into())
         }
     }
 
@@ -218,7 +241,14 @@ impl Bot {
             };
             match StructuredMessage::from_unstructured(&unstructured) {
                 Ok(msg) => {
-                    self.writer_tx.send(msg.into_owned()).await?;
+                    let msg = msg.into_owned();
+
+                    // Only clone the message when someone is following the live logs
+                    if self.app.live_messages.receiver_count() > 0 {
+                        let _ = self.app.live_messages.send(Arc::new(msg.clone()));
+                    }
+
+                    self.writer_tx.send(msg).await?;
                 }
                 Err(err) => {
                     error!("Could not convert message {unstructured:?} to be logged: {err}");
@@ -235,31 +265,34 @@ impl Bot {
         client: &TwitchClient<C>,
         sender_id: &str,
         sender_login: &str,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Option<String>> {
         debug!("Processing command {cmd}");
         let mut split = cmd.split_whitespace();
-        if let Some(action) = split.next() {
-            let args: Vec<&str> = split.collect();
-
-            match action {
-                "join" => {
-                    self.check_admin(sender_login)?;
-                    self.update_channels(client, &args, ChannelAction::Join)
-                        .await?
-                }
-                "leave" | "part" => {
-                    self.check_admin(sender_login)?;
-                    self.update_channels(client, &args, ChannelAction::Part)
-                        .await?
-                }
-                "optout" => {
-                    self.optout_user(&args, sender_login, sender_id).await?;
-                }
-                _ => (),
+        let Some(action) = split.next() else {
+            return Ok(None);
+        };
+        let args: Vec<&str> = split.collect();
+
+        let reply = match action {
+            "join" => {
+                self.check_admin(sender_login)?;
+                let channels = self
+                    .update_channels(client, &args, ChannelAction::Join)
+                    .await?;
+                format!("Joined {}", channels.join(", "))
             }
-        }
+            "leave" | "part" => {
+                self.check_admin(sender_login)?;
+                let channels = self
+                    .update_channels(client, &args, ChannelAction::Part)
+                    .await?;
+                format!("Left {}", channels.join(", "))
+            }
+            "optout" => self.optout_user(&args, sender_login, sender_id).await?,
+            _ => return Ok(None),
+        };
 
-        Ok(())
+        Ok(Some(reply))
     }
 
     async fn optout_user(
@@ -267,18 +300,18 @@ impl Bot {
         args: &[&str],
         sender_login: &str,
         sender_id: &str,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<String> {
         let arg = args.first().context("No optout code provided")?;
         if self.app.optout_codes.remove(*arg).is_some() {
             self.app.optout_user(sender_id).await?;
 
-            Ok(())
+            Ok("You have been opted out, your logs are being deleted".to_owned())
         } else if self.check_admin(sender_login).is_ok() {
             let user_id = self.app.get_user_id_by_name(arg).await?;
 
             self.app.optout_user(&user_id).await?;
 
-            Ok(())
+            Ok(format!("Opted out user {arg}"))
         } else {
             Err(anyhow!("Invalid optout code"))
         }
@@ -289,7 +322,7 @@ impl Bot {
         client: &TwitchClient<C>,
         channels: &[&str],
         action: ChannelAction,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<Vec<String>> {
         if channels.is_empty() {
             return Err(anyhow!("no channels specified"));
         }
@@ -303,6 +336,7 @@ impl Bot {
             )
             .await?;
 
+        let mut channel_names = Vec::with_capacity(channels.len());
         {
             let mut config_channels = self.app.config.channels.write().unwrap();
 
@@ -311,20 +345,74 @@ impl Bot {
                     ChannelAction::Join => {
                         info!("Joining channel {channel_name}");
                         config_channels.insert(channel_id);
-                        client.join(channel_name)?;
+                        client.join(channel_name.clone())?;
                     }
                     ChannelAction::Part => {
                         info!("Parting channel {channel_name}");
                         config_channels.remove(&channel_id);
-                        client.part(channel_name);
+                        client.part(channel_name.clone());
                     }
                 }
+                channel_names.push(channel_name);
             }
         }
 
         self.app.config.save()?;
 
-        Ok(())
+        Ok(channel_names)
+    }
+}
+
+/// Stores the bot's refreshing OAuth token in the config file
+pub struct ConfigTokenStorage {
+    config: Arc<Config>,
+}
+
+impl ConfigTokenStorage {
+    pub fn new(config: Arc<Config>) -> Self {
+        Self { config }
+    }
+}
+
+impl fmt::Debug for ConfigTokenStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfigTokenStorage").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl TokenStorage for ConfigTokenStorage {
+    type LoadError = anyhow::Error;
+    type UpdateError = anyhow::Error;
+
+    async fn load_token(&mut self) -> anyhow::Result<UserAccessToken> {
+        let token = self
+            .config
+            .bot_token
+            .read()
+            .unwrap()
+            .clone()
+            .context("No bot token configured")?;
+
+        Ok(UserAccessToken {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token.context("No refresh token configured")?,
+            // A token of unknown age gets refreshed right away
+            created_at: token.created_at.unwrap_or(DateTime::UNIX_EPOCH),
+            expires_at: token.expires_at,
+        })
+    }
+
+    async fn update_token(&mut self, token: &UserAccessToken) -> anyhow::Result<()> {
+        info!("Bot token has been refreshed");
+
+        *self.config.bot_token.write().unwrap() = Some(BotToken {
+            access_token: token.access_token.clone(),
+            refresh_token: Some(token.refresh_token.clone()),
+            created_at: Some(token.created_at),
+            expires_at: token.expires_at,
+        });
+        self.config.save()
     }
 }
 
@@ -1,4 +1,5 @@
 use anyhow::Context;
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -16,6 +17,11 @@ pub struct Config {
     pub clickhouse_password: Option<String>,
     #[serde(default = "clickhouse_flush_interval")]
     pub clickhouse_flush_interval: u64,
+    pub writer_spool_path: Option<String>,
+    pub writer_buffer_max_size: Option<usize>,
+    #[serde(default)]
+    pub writer_buffer_overflow_policy: OverflowPolicy,
+    pub writer_spill_path: Option<String>,
     #[serde(default = "default_listen_address")]
     pub listen_address: String,
     pub channels: RwLock<HashSet<String>>,
@@ -24,9 +30,46 @@ pub struct Config {
     pub client_secret: String,
     pub admins: Vec<String>,
     #[serde(default)]
+    pub known_bots: Vec<String>,
+    #[serde(default)]
+    pub stopwords: Vec<String>,
+    pub bot_login: Option<String>,
+    #[serde(default)]
+    pub bot_token: RwLock<Option<BotToken>>,
+    #[serde(default)]
     pub opt_out: DashMap<String, bool>,
+    /// How many days of logs to keep per channel id, channels which are not listed are kept forever
+    #[serde(default)]
+    pub channel_retention: DashMap<String, u32>,
     #[serde(rename = "adminAPIKey")]
     pub admin_api_key: Option<String>,
+    /// Folder the admin API exports channel archives into
+    pub archive_export_path: Option<String>,
+    /// Folder holding the partitions moved out of the database by the `archive` command
+    pub cold_archive_path: Option<String>,
+}
+
+/// OAuth token used by the bot to log in to chat
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BotToken {
+    pub access_token: String,
+    /// When set, the access token is refreshed before it expires and the new one is saved to the config
+    pub refresh_token: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// What to do with new messages when the flush buffer is full
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum OverflowPolicy {
+    /// Stop receiving messages until the buffer is flushed
+    #[default]
+    Block,
+    DropOldest,
+    /// Store new messages on disk until the buffer is flushed
+    Spill,
 }
 
 impl Config {
@@ -43,6 +86,13 @@ impl Config {
 
         Ok(())
     }
+
+    pub fn opted_out_user_ids(&self) -> Vec<String> {
+        self.opt_out
+            .iter()
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
 }
 
 fn default_listen_address() -> String {
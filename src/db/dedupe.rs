@@ -0,0 +1,237 @@
+use super::{
+    migrations::{fill_channel_activity, fill_emote_usage},
+    schema::{StructuredMessage, MESSAGES_STRUCTURED_TABLE},
+};
+use anyhow::{bail, Context};
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+use std::{collections::HashSet, ops::Range};
+use tracing::info;
+use uuid::Uuid;
+
+const DEDUPE_TABLE: &str = "__rustlog_dedupe";
+const DEDUPE_CHANNEL_ACTIVITY_TABLE: &str = "__rustlog_dedupe_channel_activity";
+const DEDUPE_EMOTE_USAGE_TABLE: &str = "__rustlog_dedupe_emote_usage";
+/// Copies of the deduplicated partitions, with the tables whose partitions they replace
+const DEDUPE_TABLES: [(&str, &str); 3] = [
+    (DEDUPE_TABLE, MESSAGES_STRUCTURED_TABLE),
+    (DEDUPE_CHANNEL_ACTIVITY_TABLE, "channel_activity"),
+    (DEDUPE_EMOTE_USAGE_TABLE, "emote_usage"),
+];
+/// How long no messages must have been logged for before duplicates can be removed
+const LOGGING_IDLE_MINUTES: u32 = 10;
+/// How many messages are looked up per query by `find_stored_keys`
+const LOOKUP_BATCH_SIZE: usize = 1000;
+const NIL_UUID_SQL: &str = "toUUID('00000000-0000-0000-0000-000000000000')";
+/// SQL equivalent of `StructuredMessage::dedupe_key`.
+/// The raw message is not stored, so messages without an id are compared by all of their columns instead
+const DEDUPE_KEY_SQL: &str = "id, if(id = toUUID('00000000-0000-0000-0000-000000000000'), cityHash64(channel_id, channel_login, timestamp, message_type, user_id, user_login, display_name, ifNull(toString(color), ''), user_type, badges, badge_info, client_nonce, emotes, automod_flags, text, message_flags, mapKeys(extra_tags), mapValues(extra_tags)), 0)";
+
+#[derive(Row, Deserialize)]
+struct IdRow {
+    #[serde(with = "clickhouse::serde::uuid")]
+    id: Uuid,
+}
+
+/// Dedupe keys of the channel's stored messages in the time range (in milliseconds)
+pub async fn read_stored_keys(
+    db: &Client,
+    channel_id: &str,
+    range: Range<u64>,
+) -> anyhow::Result<HashSet<Uuid>> {
+    let from = range.start as f64 / 1000.0;
+    let to = range.end as f64 / 1000.0;
+
+    let mut keys: HashSet<Uuid> = db
+        .query(&format!("SELECT id FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND id != {NIL_UUID_SQL}"))
+        .bind(channel_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all::<IdRow>()
+        .await
+        .context("Could not read stored message ids")?
+        .into_iter()
+        .map(|row| row.id)
+        .collect();
+
+    let messages_without_id = db
+        .query(&format!("SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND id = {NIL_UUID_SQL}"))
+        .bind(channel_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all::<StructuredMessage<'static>>()
+        .await
+        .context("Could not read stored messages without an id")?;
+    keys.extend(
+        messages_without_id
+            .iter()
+            .map(StructuredMessage::dedupe_key),
+    );
+
+    Ok(keys)
+}
+
+/// Dedupe keys of the given messages which are already stored
+pub async fn find_stored_keys<'a, 'b: 'a>(
+    db: &Client,
+    messages: impl IntoIterator<Item = &'a StructuredMessage<'b>>,
+) -> anyhow::Result<HashSet<Uuid>> {
+    let mut keys = HashSet::new();
+
+    let (with_id, without_id): (Vec<_>, Vec<_>) =
+        messages.into_iter().partition(|msg| !msg.uuid().is_nil());
+
+    // The lists are inlined into the query, which has to stay under the server's `max_query_size`
+    for batch in with_id.chunks(LOOKUP_BATCH_SIZE) {
+        let Some((from, to)) = timestamp_bounds(batch) else {
+            continue;
+        };
+        let ids: Vec<String> = batch.iter().map(|msg| msg.uuid().to_string()).collect();
+
+        let rows = db
+            .query("SELECT id FROM message_structured WHERE timestamp >= ? AND timestamp <= ? AND id IN ?")
+            .bind(from as f64 / 1000.0)
+            .bind(to as f64 / 1000.0)
+            .bind(ids)
+            .fetch_all::<IdRow>()
+            .await
+            .context("Could not look up stored message ids")?;
+        keys.extend(rows.into_iter().map(|row| row.id));
+    }
+
+    for batch in without_id.chunks(LOOKUP_BATCH_SIZE) {
+        let Some((from, to)) = timestamp_bounds(batch) else {
+            continue;
+        };
+        let channel_ids: HashSet<&str> = batch.iter().map(|msg| &*msg.channel_id).collect();
+        let timestamps: HashSet<u64> = batch.iter().map(|msg| msg.timestamp).collect();
+
+        let stored = db
+            .query(&format!("SELECT ?fields FROM message_structured WHERE channel_id IN ? AND timestamp >= ? AND timestamp <= ? AND id = {NIL_UUID_SQL} AND toUnixTimestamp64Milli(timestamp) IN ?"))
+            .bind(channel_ids.into_iter().collect::<Vec<_>>())
+            .bind(from as f64 / 1000.0)
+            .bind(to as f64 / 1000.0)
+            .bind(timestamps.into_iter().collect::<Vec<_>>())
+            .fetch_all::<StructuredMessage<'static>>()
+            .await
+            .context("Could not look up stored messages without an id")?;
+        keys.extend(stored.iter().map(StructuredMessage::dedupe_key));
+    }
+
+    Ok(keys)
+}
+
+fn timestamp_bounds(messages: &[&StructuredMessage<'_>]) -> Option<(u64, u64)> {
+    let from = messages.iter().map(|msg| msg.timestamp).min()?;
+    let to = messages.iter().map(|msg| msg.timestamp).max()?;
+    Some((from, to))
+}
+
+/// Rewrites every partition of `message_structured` that contains duplicates, keeping one copy of each message,
+/// and rebuilds the matching partitions of the aggregated tables.
+///
+/// Messages inserted into a partition while it is being rewritten would be lost, so this needs exclusive access to the database:
+/// nothing else may write to it, including rustlog itself and the `migrate`, `import` and `archive` commands.
+/// Only live logging can be detected, by refusing to run when messages were logged recently.
+/// Writes to old months can only be noticed once a partition was rewritten, by counting its rows again.
+pub async fn remove_duplicates(db: &Client) -> anyhow::Result<u64> {
+    let recent_count = db
+        .query(
+            "SELECT count() FROM message_structured WHERE timestamp > now() - toIntervalMinute(?)",
+        )
+        .bind(LOGGING_IDLE_MINUTES)
+        .fetch_one::<u64>()
+        .await?;
+    if recent_count > 0 {
+        bail!("Messages were logged in the last {LOGGING_IDLE_MINUTES} minutes, stop rustlog before removing duplicates");
+    }
+
+    let partitions = db
+        .query("SELECT DISTINCT partition_id FROM system.parts WHERE database = currentDatabase() AND table = 'message_structured' AND active ORDER BY partition_id")
+        .fetch_all::<String>()
+        .await
+        .context("Could not fetch partition list")?;
+
+    for (table, source) in DEDUPE_TABLES {
+        db.query(&format!("DROP TABLE IF EXISTS {table}"))
+            .execute()
+            .await?;
+        db.query(&format!("CREATE TABLE {table} AS {source}"))
+            .execute()
+            .await
+            .with_context(|| format!("Could not create {table}"))?;
+    }
+
+    let mut total_removed = 0;
+
+    for partition in partitions {
+        let count = count_partition(db, &partition).await?;
+        let unique_count = db
+            .query(&format!("SELECT count() FROM (SELECT 1 FROM message_structured WHERE _partition_id = ? LIMIT 1 BY {DEDUPE_KEY_SQL})"))
+            .bind(&partition)
+            .fetch_one::<u64>()
+            .await?;
+
+        if unique_count == count {
+            info!("Partition {partition} has no duplicates");
+            continue;
+        }
+
+        info!(
+            "Removing {} duplicates from partition {partition}",
+            count - unique_count
+        );
+        let month: u32 = partition
+            .parse()
+            .with_context(|| format!("Unexpected partition id {partition}"))?;
+
+        db.query(&format!("INSERT INTO {DEDUPE_TABLE} SELECT * FROM message_structured WHERE _partition_id = ? LIMIT 1 BY {DEDUPE_KEY_SQL}"))
+            .bind(&partition)
+            .execute()
+            .await
+            .with_context(|| format!("Could not copy partition {partition}"))?;
+        // Aggregated from the deduplicated copy, as the materialized views only see new inserts into `message_structured`
+        fill_channel_activity(db, DEDUPE_TABLE, DEDUPE_CHANNEL_ACTIVITY_TABLE, month)
+            .await
+            .with_context(|| {
+                format!("Could not aggregate channel activity of partition {partition}")
+            })?;
+        fill_emote_usage(db, DEDUPE_TABLE, DEDUPE_EMOTE_USAGE_TABLE, month)
+            .await
+            .with_context(|| format!("Could not aggregate emote usage of partition {partition}"))?;
+
+        if count_partition(db, &partition).await? != count {
+            bail!("Partition {partition} was modified while removing duplicates, stop rustlog before running this");
+        }
+
+        for (table, source) in DEDUPE_TABLES {
+            db.query(&format!(
+                "ALTER TABLE {source} REPLACE PARTITION ID ? FROM {table}"
+            ))
+            .bind(&partition)
+            .execute()
+            .await
+            .with_context(|| format!("Could not replace partition {partition} of {source}"))?;
+            db.query(&format!("TRUNCATE TABLE {table}"))
+                .execute()
+                .await?;
+        }
+
+        total_removed += count - unique_count;
+    }
+
+    for (table, _) in DEDUPE_TABLES {
+        db.query(&format!("DROP TABLE {table}")).execute().await?;
+    }
+
+    Ok(total_removed)
+}
+
+async fn count_partition(db: &Client, partition: &str) -> anyhow::Result<u64> {
+    let count = db
+        .query("SELECT count() FROM message_structured WHERE _partition_id = ?")
+        .bind(partition)
+        .fetch_one::<u64>()
+        .await?;
+    Ok(count)
+}
@@ -0,0 +1,83 @@
+use super::migratable::Migratable;
+use anyhow::Context;
+use tracing::info;
+
+const ACTIVITY_SELECT: &str = "
+    SELECT
+        channel_id,
+        toStartOfMinute(timestamp) AS bucket,
+        countIf(message_type = 1) AS messages,
+        uniqStateIf(user_id, message_type = 1 AND user_id != '') AS chatters,
+        countIf(message_type = 4 AND extra_tags['msg-id'] IN ('sub', 'resub', 'subgift')) AS subs,
+        countIf(message_type = 4 AND extra_tags['msg-id'] = 'raid') AS raids,
+        countIf(message_type = 2 AND user_id != '' AND extra_tags['ban-duration'] = '') AS bans
+";
+
+/// Inserts the channel activity of the messages in `source` from the month (in the `toYYYYMM` format) into `target`
+pub async fn fill_channel_activity(
+    db: &clickhouse::Client,
+    source: &str,
+    target: &str,
+    month: u32,
+) -> clickhouse::error::Result<()> {
+    db.query(&format!(
+        "INSERT INTO {target} {ACTIVITY_SELECT} FROM {source} WHERE toYYYYMM(timestamp) = ? GROUP BY channel_id, bucket"
+    ))
+    .bind(month)
+    .execute()
+    .await
+}
+
+pub struct ChannelActivityMigration;
+
+impl<'a> Migratable<'a> for ChannelActivityMigration {
+    async fn run(&self, db: &'a clickhouse::Client) -> anyhow::Result<()> {
+        let partitions = db
+            .query("SELECT DISTINCT toYYYYMM(timestamp) as partition FROM message_structured ORDER BY partition ASC")
+            .fetch_all::<u32>()
+            .await
+            .context("Could not fetch partition list")?;
+
+        db.query(
+            "
+            CREATE TABLE channel_activity
+            (
+                channel_id LowCardinality(String),
+                bucket DateTime CODEC(DoubleDelta, ZSTD(5)),
+                messages SimpleAggregateFunction(sum, UInt64),
+                chatters AggregateFunction(uniq, String),
+                subs SimpleAggregateFunction(sum, UInt64),
+                raids SimpleAggregateFunction(sum, UInt64),
+                bans SimpleAggregateFunction(sum, UInt64)
+            )
+            ENGINE = AggregatingMergeTree
+            PARTITION BY toYYYYMM(bucket)
+            ORDER BY (channel_id, bucket)
+        ",
+        )
+        .execute()
+        .await?;
+
+        info!(
+            "Filling channel activity from {} partitions",
+            partitions.len()
+        );
+
+        for partition in partitions {
+            info!("Filling channel activity for partition {partition}");
+            fill_channel_activity(db, "message_structured", "channel_activity", partition)
+                .await
+                .context("Could not fill channel activity")?;
+        }
+
+        db.query(&format!(
+            "CREATE MATERIALIZED VIEW channel_activity_mv TO channel_activity AS {ACTIVITY_SELECT} FROM message_structured GROUP BY channel_id, bucket"
+        ))
+        .execute()
+        .await?;
+
+        info!("Channel activity built");
+
+        Ok(())
+    }
+}
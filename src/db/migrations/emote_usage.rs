@@ -0,0 +1,100 @@
+use super::migratable::Migratable;
+use anyhow::Context;
+use tracing::info;
+
+/// Splits the `emotes` tag (`id:start-end,start-end/id:start-end`) into one row per emote,
+/// taking the emote name from the message text at its first range
+const EMOTE_USAGE_SELECT: &str = "
+    WITH
+        splitByChar(':', emote) AS emote_parts,
+        splitByChar(',', emote_parts[2]) AS ranges,
+        splitByChar('-', ranges[1]) AS first_range,
+        if(
+            startsWith(text, '\\x01ACTION ') AND endsWith(text, '\\x01'),
+            substring(text, 9, length(text) - 9),
+            text
+        ) AS message_text
+    SELECT
+        channel_id,
+        user_id,
+        toStartOfHour(timestamp) AS bucket,
+        emote_parts[1] AS emote_id,
+        anyLast(substringUTF8(
+            message_text,
+            toUInt64OrZero(first_range[1]) + 1,
+            toUInt64OrZero(first_range[2]) - toUInt64OrZero(first_range[1]) + 1
+        )) AS emote_name,
+        sum(toUInt64(length(ranges))) AS uses
+";
+
+const EMOTE_USAGE_FILTER: &str = "
+    ARRAY JOIN splitByChar('/', emotes) AS emote
+    WHERE message_type = 1 AND emotes != ''
+";
+
+const EMOTE_USAGE_GROUP_BY: &str = "GROUP BY channel_id, user_id, bucket, emote_id";
+
+/// Inserts the emote usage of the messages in `source` from the month (in the `toYYYYMM` format) into `target`
+pub async fn fill_emote_usage(
+    db: &clickhouse::Client,
+    source: &str,
+    target: &str,
+    month: u32,
+) -> clickhouse::error::Result<()> {
+    db.query(&format!(
+        "INSERT INTO {target} {EMOTE_USAGE_SELECT} FROM {source} {EMOTE_USAGE_FILTER} AND toYYYYMM(timestamp) = ? {EMOTE_USAGE_GROUP_BY}"
+    ))
+    .bind(month)
+    .execute()
+    .await
+}
+
+pub struct EmoteUsageMigration;
+
+impl<'a> Migratable<'a> for EmoteUsageMigration {
+    async fn run(&self, db: &'a clickhouse::Client) -> anyhow::Result<()> {
+        let partitions = db
+            .query("SELECT DISTINCT toYYYYMM(timestamp) as partition FROM message_structured ORDER BY partition ASC")
+            .fetch_all::<u32>()
+            .await
+            .context("Could not fetch partition list")?;
+
+        db.query(
+            "
+            CREATE TABLE emote_usage
+            (
+                channel_id LowCardinality(String),
+                user_id String CODEC(ZSTD(5)),
+                bucket DateTime CODEC(DoubleDelta, ZSTD(5)),
+                emote_id String CODEC(ZSTD(5)),
+                emote_name SimpleAggregateFunction(anyLast, String) CODEC(ZSTD(5)),
+                uses SimpleAggregateFunction(sum, UInt64)
+            )
+            ENGINE = AggregatingMergeTree
+            PARTITION BY toYYYYMM(bucket)
+            ORDER BY (channel_id, user_id, emote_id, bucket)
+        ",
+        )
+        .execute()
+        .await?;
+
+        info!("Filling emote usage from {} partitions", partitions.len());
+
+        for partition in partitions {
+            info!("Filling emote usage for partition {partition}");
+            fill_emote_usage(db, "message_structured", "emote_usage", partition)
+                .await
+                .context("Could not fill emote usage")?;
+        }
+
+        db.query(&format!(
+            "CREATE MATERIALIZED VIEW emote_usage_mv TO emote_usage AS {EMOTE_USAGE_SELECT} FROM message_structured {EMOTE_USAGE_FILTER} {EMOTE_USAGE_GROUP_BY}"
+        ))
+        .execute()
+        .await?;
+
+        info!("Emote usage built");
+
+        Ok(())
+    }
+}
@@ -1,15 +1,22 @@
+mod channel_activity;
+mod emote_usage;
 mod migratable;
 mod structured;
 mod username_history;
 
 use crate::Result;
+use channel_activity::ChannelActivityMigration;
 use clickhouse::Client;
+use emote_usage::EmoteUsageMigration;
 use structured::StructuredMigration;
 use tracing::{debug, info};
 use username_history::UsernameHistoryMigration;
 
 use self::migratable::Migratable;
 
+pub use channel_activity::fill_channel_activity;
+pub use emote_usage::fill_emote_usage;
+
 pub async fn run(db: &Client, db_name: &str) -> Result<()> {
     create_migrations_table(db).await?;
 
@@ -75,6 +82,79 @@ String CODEC(ZSTD(10))
 
     run_migration(db, "7_username_history", UsernameHistoryMigration).await?;
 
+    run_migration(
+        db,
+        "8_message_id_index",
+        "ALTER TABLE message_structured ADD INDEX IF NOT EXISTS id_bloom_filter id TYPE bloom_filter GRANULARITY 4",
+    )
+    .await?;
+
+    run_migration(db, "9_channel_activity", ChannelActivityMigration).await?;
+
+    run_migration(db, "10_emote_usage", EmoteUsageMigration).await?;
+
+    run_migration(
+        db,
+        "11_migration_checkpoints",
+        "
+CREATE TABLE IF NOT EXISTS __rustlog_migration_checkpoints
+(
+    channel_id String,
+    date Date,
+    file_size UInt64,
+    line_count UInt64,
+    completed_at DateTime
+)
+ENGINE = ReplacingMergeTree(completed_at)
+ORDER BY (channel_id, date)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "12_archived_partitions",
+        "
+CREATE TABLE IF NOT EXISTS __rustlog_archived_partitions
+(
+    month UInt32,
+    archived_at DateTime
+)
+ENGINE = MergeTree
+ORDER BY month",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "13_user_purges",
+        "
+CREATE TABLE IF NOT EXISTS __rustlog_user_purges
+(
+    user_id String,
+    started_at DateTime,
+    finished_at Nullable(DateTime),
+    updated_at DateTime64(3)
+)
+ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY user_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "14_archived_user_days",
+        "
+CREATE TABLE IF NOT EXISTS __rustlog_archived_user_days
+(
+    user_id String,
+    channel_id LowCardinality(String),
+    day Date
+)
+ENGINE = ReplacingMergeTree
+ORDER BY (user_id, channel_id, day)",
+    )
+    .await?;
+
     Ok(())
 }
 
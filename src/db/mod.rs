@@ -1,5 +1,7 @@
+pub mod dedupe;
 mod migrations;
 pub mod schema;
+mod spool;
 pub mod writer;
 use std::collections::HashSet;
 
@@ -10,33 +12,50 @@ use writer::FlushBuffer;
 use crate::{
     error::Error,
     logs::{
+        deletions::MessageDeletions,
+        pagination::PageCursor,
         schema::LogRangeParams,
+        search::SearchQuery,
         stream::{FlushBufferResponse, LogsStream},
     },
-    web::schema::{AvailableLogDate, LogsParams, PreviousName, UserLogsStats},
+    web::schema::{
+        AvailableLogDate, ChannelActivityBucket, DeletedFilter, EmoteUsage, LeaderboardRanking,
+        LogsParams, PreviousName, TimeseriesInterval, UserLogsStats, WordUsage,
+    },
     Result,
 };
 use chrono::{DateTime, Datelike, Duration, Utc};
-use clickhouse::{query::RowCursor, Client, Row};
+use clickhouse::{
+    query::{BytesCursor, Query, RowCursor},
+    Client, Row,
+};
 use rand::{rng, seq::IteratorRandom};
-use schema::StructuredMessage;
-use tracing::debug;
+use schema::{MessageType, StructuredMessage, MESSAGES_STRUCTURED_TABLE};
+use tracing::{debug, info};
+use uuid::Uuid;
 
 const CHANNEL_MULTI_QUERY_SIZE_DAYS: i64 = 14;
+/// Twitch only allows deleting messages for this long after they were sent
+const MESSAGE_DELETION_WINDOW_HOURS: i64 = 6;
+/// How far the actual time of a message can be from the timestamp hint of a message lookup
+const MESSAGE_TIMESTAMP_HINT_TOLERANCE_SECONDS: i64 = 60;
+/// `text` of a `PRIVMSG` without the `/me` wrapping
+const MESSAGE_TEXT_SQL: &str = "if(startsWith(text, '\\x01ACTION ') AND endsWith(text, '\\x01'), substring(text, 9, length(text) - 9), text)";
 
 pub async fn read_channel(
     db: &Client,
     channel_id: &str,
     params: LogsParams,
     flush_buffer: &FlushBuffer,
-    (from, to): (DateTime<Utc>, DateTime<Utc>),
+    range: (DateTime<Utc>, DateTime<Utc>),
 ) -> Result<LogsStream> {
+    let (from, to) = narrow_range(&params, range);
     let buffer_response =
         FlushBufferResponse::new(flush_buffer, channel_id, None, params, (from, to)).await;
 
-    let suffix = if params.reverse { "DESC" } else { "ASC" };
-
-    let mut query = format!("SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? ORDER BY timestamp {suffix}");
+    let deleted = deleted_condition(params.deleted);
+    let pagination = pagination_clause(&params);
+    let mut query = format!("SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ?{deleted}{pagination}");
 
     if to - from > Duration::days(CHANNEL_MULTI_QUERY_SIZE_DAYS) {
         let count = db
@@ -49,60 +68,92 @@ pub async fn read_channel(
             return Err(Error::NotFound);
         }
 
-        let mut streams = Vec::with_capacity(1);
-
-        let interval = Duration::days(CHANNEL_MULTI_QUERY_SIZE_DAYS);
+        let streams = multi_query_cursors(db, &query, channel_id, None, params, (from, to))?;
+        LogsStream::new_multi_query(streams, buffer_response)
+    } else {
+        apply_limit_offset(&mut query, &buffer_response);
 
-        let mut current_from = from;
-        let mut current_to = current_from + interval;
+        let cursor = next_cursor(db, &query, channel_id, None, params.deleted, from, to)?;
+        LogsStream::new_cursor(cursor, buffer_response).await
+    }
+}
 
-        loop {
-            let cursor = next_cursor(db, &query, channel_id, current_from, current_to)?;
+/// Splits the range into `CHANNEL_MULTI_QUERY_SIZE_DAYS` sized queries
+fn multi_query_cursors(
+    db: &Client,
+    query: &str,
+    channel_id: &str,
+    search: Option<&SearchQuery>,
+    params: LogsParams,
+    (from, to): (DateTime<Utc>, DateTime<Utc>),
+) -> Result<Vec<RowCursor<StructuredMessage<'static>>>> {
+    let mut streams = Vec::with_capacity(1);
+
+    let interval = Duration::days(CHANNEL_MULTI_QUERY_SIZE_DAYS);
+
+    let mut current_from = from;
+    let mut current_to = current_from + interval;
+
+    loop {
+        let cursor = next_cursor(
+            db,
+            query,
+            channel_id,
+            search,
+            params.deleted,
+            current_from,
+            current_to,
+        )?;
+        streams.push(cursor);
+
+        current_from += interval;
+        current_to += interval;
+
+        if current_to > to {
+            let cursor = next_cursor(
+                db,
+                query,
+                channel_id,
+                search,
+                params.deleted,
+                current_from,
+                to,
+            )?;
             streams.push(cursor);
-
-            current_from += interval;
-            current_to += interval;
-
-            if current_to > to {
-                let cursor = next_cursor(db, &query, channel_id, current_from, to)?;
-                streams.push(cursor);
-                break;
-            }
-        }
-
-        if params.reverse {
-            streams.reverse();
+            break;
         }
+    }
 
-        debug!("Using {} queries for multi-query stream", streams.len());
+    if params.reverse {
+        streams.reverse();
+    }
 
-        LogsStream::new_multi_query(streams, buffer_response)
-    } else {
-        apply_limit_offset(&mut query, &buffer_response);
+    debug!("Using {} queries for multi-query stream", streams.len());
 
-        let cursor = db
-            .query(&query)
-            .bind(channel_id)
-            .bind(from.timestamp_millis() as f64 / 1000.0)
-            .bind(to.timestamp_millis() as f64 / 1000.0)
-            .fetch()?;
-        LogsStream::new_cursor(cursor, buffer_response).await
-    }
+    Ok(streams)
 }
 
 fn next_cursor(
     db: &Client,
     query: &str,
     channel_id: &str,
+    search: Option<&SearchQuery>,
+    deleted: Option<DeletedFilter>,
     from: DateTime<Utc>,
     to: DateTime<Utc>,
 ) -> Result<RowCursor<StructuredMessage<'static>>> {
-    let cursor = db
+    let mut query = db
         .query(query)
         .bind(channel_id)
         .bind(from.timestamp_millis() as f64 / 1000.0)
-        .bind(to.timestamp_millis() as f64 / 1000.0)
-        .fetch()?;
+        .bind(to.timestamp_millis() as f64 / 1000.0);
+
+    if let Some(search) = search {
+        query = search.bind(query);
+    }
+    let query = bind_deleted_filter(query, deleted, channel_id, (from, to));
+
+    let cursor = query.fetch()?;
     Ok(cursor)
 }
 
@@ -112,22 +163,24 @@ pub async fn read_user(
     user_id: &str,
     params: LogsParams,
     flush_buffer: &FlushBuffer,
-    (from, to): (DateTime<Utc>, DateTime<Utc>),
+    range: (DateTime<Utc>, DateTime<Utc>),
 ) -> Result<LogsStream> {
+    let (from, to) = narrow_range(&params, range);
     let buffer_response =
         FlushBufferResponse::new(flush_buffer, channel_id, Some(user_id), params, (from, to)).await;
 
-    let suffix = if params.reverse { "DESC" } else { "ASC" };
-    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND timestamp >= ? AND timestamp < ? ORDER BY timestamp {suffix}");
+    let deleted = deleted_condition(params.deleted);
+    let pagination = pagination_clause(&params);
+    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND timestamp >= ? AND timestamp < ?{deleted}{pagination}");
     apply_limit_offset(&mut query, &buffer_response);
 
-    let cursor = db
+    let query = db
         .query(&query)
         .bind(channel_id)
         .bind(user_id)
         .bind(from.timestamp_millis() as f64 / 1000.0)
-        .bind(to.timestamp_millis() as f64 / 1000.0)
-        .fetch()?;
+        .bind(to.timestamp_millis() as f64 / 1000.0);
+    let cursor = bind_deleted_filter(query, params.deleted, channel_id, (from, to)).fetch()?;
     LogsStream::new_cursor(cursor, buffer_response).await
 }
 
@@ -158,6 +211,74 @@ pub async fn read_available_channel_logs(
     Ok(dates)
 }
 
+/// Start of every day with logs in the channel, oldest first
+pub async fn read_channel_log_days(db: &Client, channel_id: &str) -> Result<Vec<DateTime<Utc>>> {
+    let timestamps: Vec<i32> = db
+        .query(
+            "SELECT toDateTime(toStartOfDay(timestamp)) AS date FROM message_structured WHERE channel_id = ? GROUP BY date ORDER BY date ASC",
+        )
+        .bind(channel_id)
+        .fetch_all().await?;
+
+    Ok(timestamps
+        .into_iter()
+        .map(|timestamp| DateTime::from_timestamp(timestamp.into(), 0).expect("Invalid DateTime"))
+        .collect())
+}
+
+/// All messages of the channel in the given day except the ones of the given users, in chronological order
+pub fn read_channel_day(
+    db: &Client,
+    channel_id: &str,
+    excluded_user_ids: &[String],
+    day: DateTime<Utc>,
+) -> Result<RowCursor<StructuredMessage<'static>>> {
+    let mut query = "SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ?".to_owned();
+    if !excluded_user_ids.is_empty() {
+        query.push_str(" AND user_id NOT IN ?");
+    }
+    query.push_str(" ORDER BY timestamp ASC, id ASC");
+
+    let mut query = db
+        .query(&query)
+        .bind(channel_id)
+        .bind(day.timestamp())
+        .bind((day + Duration::days(1)).timestamp());
+    if !excluded_user_ids.is_empty() {
+        query = query.bind(excluded_user_ids);
+    }
+    Ok(query.fetch()?)
+}
+
+/// Streams the channel's messages as a Parquet file, leaving out the given users
+pub fn read_channel_parquet(
+    db: &Client,
+    channel_id: &str,
+    excluded_user_ids: Vec<String>,
+    range_params: LogRangeParams,
+) -> Result<BytesCursor> {
+    let mut query = "SELECT * FROM message_structured WHERE channel_id = ?".to_owned();
+    if !excluded_user_ids.is_empty() {
+        query.push_str(" AND user_id NOT IN ?");
+    }
+    if range_params.range().is_some() {
+        query.push_str(" AND timestamp >= ? AND timestamp < ?");
+    }
+    query.push_str(" ORDER BY timestamp ASC SETTINGS output_format_parquet_string_as_string = 1, output_format_parquet_compression_method = 'zstd'");
+
+    let mut query = db.query(&query).bind(channel_id);
+    if !excluded_user_ids.is_empty() {
+        query = query.bind(excluded_user_ids);
+    }
+    if let Some((from, to)) = range_params.range() {
+        query = query
+            .bind(from.timestamp_millis() as f64 / 1000.0)
+            .bind(to.timestamp_millis() as f64 / 1000.0);
+    }
+
+    Ok(query.fetch_bytes("Parquet")?)
+}
+
 pub async fn read_available_user_logs(
     db: &Client,
     channel_id: &str,
@@ -185,6 +306,27 @@ pub async fn read_available_user_logs(
     Ok(dates)
 }
 
+/// Time range spanning all of the user's messages in the channel
+pub async fn read_user_log_range(
+    db: &Client,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let (count, first, last) = db
+        .query("SELECT count(), toUInt64(toUnixTimestamp64Milli(min(timestamp))), toUInt64(toUnixTimestamp64Milli(max(timestamp))) FROM message_structured WHERE channel_id = ? AND user_id = ?")
+        .bind(channel_id)
+        .bind(user_id)
+        .fetch_one::<(u64, u64, u64)>()
+        .await?;
+    if count == 0 {
+        return Err(Error::NotFound);
+    }
+
+    let from = DateTime::from_timestamp_millis(first as i64).ok_or(Error::NotFound)?;
+    let to = DateTime::from_timestamp_millis(last as i64 + 1).ok_or(Error::NotFound)?;
+    Ok((from, to))
+}
+
 pub async fn read_random_user_line(
     db: &Client,
     channel_id: &str,
@@ -261,39 +403,239 @@ pub async fn read_random_channel_line(
     Ok(msg)
 }
 
-pub async fn delete_user_logs(_db: &Client, _user_id: &str) -> Result<()> {
-    // info!("Deleting all logs for user {user_id}");
-    // db.query("ALTER TABLE message DELETE WHERE user_id = ?")
-    //     .bind(user_id)
-    //     .execute()
-    //     .await?;
+/// Reads a message along with up to `before` and `after` messages around it in the channel
+pub async fn read_message_with_context(
+    db: &Client,
+    channel_id: &str,
+    id: Uuid,
+    timestamp_hint: Option<DateTime<Utc>>,
+    (before, after): (u64, u64),
+    flush_buffer: &FlushBuffer,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    let message = match flush_buffer.message_by_id(channel_id, id).await {
+        Some(msg) => msg,
+        None => {
+            let mut query =
+                "SELECT * FROM message_structured WHERE channel_id = ? AND id = toUUID(?)"
+                    .to_owned();
+            if timestamp_hint.is_some() {
+                query.push_str(" AND timestamp >= ? AND timestamp < ?");
+            }
+            query.push_str(" LIMIT 1");
+
+            let mut query = db
+                .query(&query)
+                .bind(channel_id)
+                .bind(id.hyphenated().to_string());
+            if let Some(timestamp) = timestamp_hint {
+                let tolerance = Duration::seconds(MESSAGE_TIMESTAMP_HINT_TOLERANCE_SECONDS);
+                query = query
+                    .bind((timestamp - tolerance).timestamp_millis() as f64 / 1000.0)
+                    .bind((timestamp + tolerance).timestamp_millis() as f64 / 1000.0);
+            }
+            query.fetch_optional().await?.ok_or(Error::NotFound)?
+        }
+    };
+    let cursor = PageCursor::from_message(&message);
+
+    let mut messages_before =
+        read_around_cursor(db, channel_id, cursor, before, true, flush_buffer).await?;
+    messages_before.reverse();
+    let messages_after =
+        read_around_cursor(db, channel_id, cursor, after, false, flush_buffer).await?;
+
+    let mut messages = messages_before;
+    messages.push(message);
+    messages.extend(messages_after);
+    Ok(messages)
+}
+
+/// Reads up to `count` channel messages following the cursor, in the given direction
+async fn read_around_cursor(
+    db: &Client,
+    channel_id: &str,
+    cursor: PageCursor,
+    count: u64,
+    reverse: bool,
+    flush_buffer: &FlushBuffer,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+
+    let suffix = if reverse { "DESC" } else { "ASC" };
+    let condition = cursor.sql_condition(reverse);
+    let query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND {condition} ORDER BY timestamp {suffix}, id {suffix} LIMIT {count}");
+
+    let mut messages: Vec<StructuredMessage<'static>> =
+        db.query(&query).bind(channel_id).fetch_all().await?;
+
+    let buffered = flush_buffer
+        .messages_by_channel(0..u64::MAX, channel_id)
+        .await
+        .into_iter()
+        .filter(|msg| cursor.is_followed_by(msg, reverse));
+    messages.extend(buffered);
+
+    messages.sort_by_key(|msg| (msg.timestamp, msg.uuid()));
+    if reverse {
+        messages.reverse();
+    }
+    messages.truncate(count as usize);
+
+    Ok(messages)
+}
+
+/// Tables holding per-user data, cleared when a user opts out
+pub const USER_LOGS_TABLES: [&str; 3] =
+    [MESSAGES_STRUCTURED_TABLE, "username_history", "emote_usage"];
+
+/// Submits the deletion mutations without waiting for them to complete
+pub async fn delete_user_logs(db: &Client, user_id: &str) -> Result<()> {
+    info!("Deleting all logs for user {user_id}");
+
+    for table in USER_LOGS_TABLES {
+        db.query(&format!("ALTER TABLE {table} DELETE WHERE user_id = ?"))
+            .bind(user_id)
+            .execute()
+            .await?;
+    }
+
     Ok(())
 }
 
+#[derive(Row, Deserialize)]
+pub struct DeletionStatus {
+    /// How many of the user's deletion mutations are known to the database
+    pub mutation_count: u64,
+    pub is_done: bool,
+    /// How many parts still need to be processed
+    pub parts_to_do: u64,
+    pub fail_reason: String,
+}
+
+/// Reads the state of the deletion mutations of the given user
+pub async fn get_user_logs_deletion_status(db: &Client, user_id: &str) -> Result<DeletionStatus> {
+    let status = db
+        .query("SELECT count() AS mutation_count, countIf(NOT is_done) = 0 AS is_done, toUInt64(sum(parts_to_do)) AS parts_to_do, anyIf(latest_fail_reason, latest_fail_reason != '') AS fail_reason FROM system.mutations WHERE database = currentDatabase() AND command LIKE ?")
+        .bind(format!("%DELETE WHERE user_id = '{user_id}'%"))
+        .fetch_one::<DeletionStatus>()
+        .await?;
+    Ok(status)
+}
+
+/// Deletes the channel's messages older than the cutoff with lightweight deletes,
+/// along with the channel activity and emote usage buckets which only cover expired messages.
+/// Returns whether there were expired messages
+pub async fn delete_channel_logs_before(
+    db: &Client,
+    channel_id: &str,
+    cutoff: DateTime<Utc>,
+) -> Result<bool> {
+    let expired_count = db
+        .query(&format!("SELECT count() FROM (SELECT 1 FROM {MESSAGES_STRUCTURED_TABLE} WHERE channel_id = ? AND timestamp < ? LIMIT 1)"))
+        .bind(channel_id)
+        .bind(cutoff.timestamp_millis() as f64 / 1000.0)
+        .fetch_one::<u64>()
+        .await?;
+    if expired_count == 0 {
+        return Ok(false);
+    }
+
+    debug!("Deleting logs of channel {channel_id} before {cutoff}");
+    db.query(&format!(
+        "DELETE FROM {MESSAGES_STRUCTURED_TABLE} WHERE channel_id = ? AND timestamp < ?"
+    ))
+    .bind(channel_id)
+    .bind(cutoff.timestamp_millis() as f64 / 1000.0)
+    .execute()
+    .await?;
+
+    // The buckets are the start of the minute or hour, the one containing the cutoff still has messages that are kept
+    for (table, bucket_start) in [
+        ("channel_activity", "toStartOfMinute"),
+        ("emote_usage", "toStartOfHour"),
+    ] {
+        db.query(&format!(
+            "DELETE FROM {table} WHERE channel_id = ? AND bucket < {bucket_start}(toDateTime(?, 'UTC'))"
+        ))
+        .bind(channel_id)
+        .bind(cutoff.timestamp())
+        .execute()
+        .await?;
+    }
+
+    Ok(true)
+}
+
 pub async fn search_user_logs(
     db: &Client,
     channel_id: &str,
     user_id: &str,
-    search: &str,
+    search: &SearchQuery,
     params: LogsParams,
+    range: (DateTime<Utc>, DateTime<Utc>),
 ) -> Result<LogsStream> {
+    let (from, to) = narrow_range(&params, range);
     let buffer_response = FlushBufferResponse::empty(params);
 
-    let suffix = if params.reverse { "DESC" } else { "ASC" };
-
-    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND positionCaseInsensitive(text, ?) != 0 ORDER BY timestamp {suffix}");
+    search.check_regexes(db).await?;
+    let condition = search.sql_condition();
+    let deleted = deleted_condition(params.deleted);
+    let pagination = pagination_clause(&params);
+    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND timestamp >= ? AND timestamp < ? AND {condition}{deleted}{pagination}");
     apply_limit_offset(&mut query, &buffer_response);
 
-    let cursor = db
+    let query = db
         .query(&query)
         .bind(channel_id)
         .bind(user_id)
-        .bind(search)
-        .fetch()?;
+        .bind(from.timestamp_millis() as f64 / 1000.0)
+        .bind(to.timestamp_millis() as f64 / 1000.0);
+    let query = search.bind(query);
+    let cursor = bind_deleted_filter(query, params.deleted, channel_id, (from, to)).fetch()?;
 
     LogsStream::new_cursor(cursor, buffer_response).await
 }
 
+pub async fn search_channel_logs(
+    db: &Client,
+    channel_id: &str,
+    search: &SearchQuery,
+    params: LogsParams,
+    flush_buffer: &FlushBuffer,
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Result<LogsStream> {
+    let (from, to) = narrow_range(&params, range);
+    let buffer_response =
+        FlushBufferResponse::new_search(flush_buffer, channel_id, search, params, (from, to)).await;
+
+    search.check_regexes(db).await?;
+    let condition = search.sql_condition();
+    let deleted = deleted_condition(params.deleted);
+    let pagination = pagination_clause(&params);
+    let mut query = format!("SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND {condition}{deleted}{pagination}");
+
+    if to - from > Duration::days(CHANNEL_MULTI_QUERY_SIZE_DAYS) {
+        let streams =
+            multi_query_cursors(db, &query, channel_id, Some(search), params, (from, to))?;
+        LogsStream::new_multi_query(streams, buffer_response)
+    } else {
+        apply_limit_offset(&mut query, &buffer_response);
+
+        let cursor = next_cursor(
+            db,
+            &query,
+            channel_id,
+            Some(search),
+            params.deleted,
+            from,
+            to,
+        )?;
+        LogsStream::new_cursor(cursor, buffer_response).await
+    }
+}
+
 #[derive(Deserialize, Row)]
 pub struct StatsRow {
     pub cnt: u64,
@@ -343,6 +685,227 @@ pub async fn get_channel_stats(
     Ok((total_count, stats_rows))
 }
 
+/// Returns the user ids and scores of the given leaderboard page
+pub async fn get_channel_leaderboard(
+    db: &Client,
+    channel_id: &str,
+    ranking: LeaderboardRanking,
+    message_type: Option<MessageType>,
+    excluded_logins: &[String],
+    range_params: LogRangeParams,
+    (limit, offset): (u64, u64),
+) -> Result<Vec<(String, u64)>> {
+    #[derive(Deserialize, Row)]
+    struct LeaderboardRow {
+        user_id: String,
+        score: u64,
+    }
+
+    let mut query = format!(
+        "SELECT user_id, toUInt64({}) AS score FROM message_structured WHERE channel_id = ? AND user_id != ''",
+        ranking.sql_score()
+    );
+
+    if let Some(message_type) = message_type {
+        query.push_str(&format!(" AND message_type = {}", message_type as u8));
+    }
+    if !excluded_logins.is_empty() {
+        query.push_str(" AND user_login NOT IN ?");
+    }
+    if range_params.range().is_some() {
+        query.push_str(" AND timestamp >= ? AND timestamp < ?");
+    }
+    query.push_str(&format!(
+        " GROUP BY user_id ORDER BY score DESC, user_id ASC LIMIT {limit} OFFSET {offset}"
+    ));
+
+    let mut query = db.query(&query).bind(channel_id);
+
+    if !excluded_logins.is_empty() {
+        query = query.bind(excluded_logins);
+    }
+    if let Some((from, to)) = range_params.range() {
+        query = query
+            .bind(from.timestamp_millis() as f64 / 1000.0)
+            .bind(to.timestamp_millis() as f64 / 1000.0);
+    }
+
+    let rows = query.fetch_all::<LeaderboardRow>().await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.user_id, row.score))
+        .collect())
+}
+
+/// Returns the most used emotes in the channel, or by a single user in it.
+/// The range is rounded to whole hours.
+pub async fn get_emote_usage(
+    db: &Client,
+    channel_id: &str,
+    user_id: Option<&str>,
+    range_params: LogRangeParams,
+    limit: u64,
+) -> Result<Vec<EmoteUsage>> {
+    #[derive(Deserialize, Row)]
+    struct EmoteUsageRow {
+        emote_id: String,
+        emote_name: String,
+        uses: u64,
+    }
+
+    let mut query = "SELECT emote_id, anyLast(emote_name) AS emote_name, sum(uses) AS uses FROM emote_usage WHERE channel_id = ?".to_owned();
+
+    if user_id.is_some() {
+        query.push_str(" AND user_id = ?");
+    }
+    if range_params.range().is_some() {
+        query.push_str(" AND bucket >= toStartOfHour(toDateTime(?)) AND bucket < toDateTime(?)");
+    }
+    query.push_str(&format!(
+        " GROUP BY emote_id ORDER BY uses DESC, emote_id ASC LIMIT {limit}"
+    ));
+
+    let mut query = db.query(&query).bind(channel_id);
+
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    if let Some((from, to)) = range_params.range() {
+        query = query.bind(from.timestamp()).bind(to.timestamp());
+    }
+
+    let rows = query.fetch_all::<EmoteUsageRow>().await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| EmoteUsage {
+            emote_id: row.emote_id,
+            emote_name: row.emote_name,
+            count: row.uses,
+        })
+        .collect())
+}
+
+/// Returns the most used words or n-grams in the channel's chat messages, or a single user's.
+/// Phrases starting or ending with a stopword are skipped.
+pub async fn get_word_usage(
+    db: &Client,
+    channel_id: &str,
+    user_id: Option<&str>,
+    stopwords: &[String],
+    range_params: LogRangeParams,
+    (ngram, limit): (u8, u64),
+) -> Result<Vec<WordUsage>> {
+    #[derive(Deserialize, Row)]
+    struct WordUsageRow {
+        phrase: String,
+        uses: u64,
+    }
+
+    let mut query = format!(
+        "WITH
+            ? AS stopwords,
+            tokens(lowerUTF8({MESSAGE_TEXT_SQL})) AS words,
+            arrayMap(
+                i -> arraySlice(words, i, {ngram}),
+                range(1, toUInt64(greatest(toInt64(length(words)) - {ngram} + 2, 1)))
+            ) AS grams
+        SELECT
+            arrayJoin(arrayMap(
+                gram -> arrayStringConcat(gram, ' '),
+                arrayFilter(gram -> NOT has(stopwords, gram[1]) AND NOT has(stopwords, gram[-1]), grams)
+            )) AS phrase,
+            count() AS uses
+        FROM message_structured
+        WHERE channel_id = ? AND message_type = {}",
+        MessageType::PrivMsg as u8
+    );
+
+    if user_id.is_some() {
+        query.push_str(" AND user_id = ?");
+    }
+    if range_params.range().is_some() {
+        query.push_str(" AND timestamp >= ? AND timestamp < ?");
+    }
+    query.push_str(&format!(
+        " GROUP BY phrase ORDER BY uses DESC, phrase ASC LIMIT {limit}"
+    ));
+
+    let stopwords: Vec<String> = stopwords.iter().map(|word| word.to_lowercase()).collect();
+    let mut query = db.query(&query).bind(stopwords).bind(channel_id);
+
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    if let Some((from, to)) = range_params.range() {
+        query = query
+            .bind(from.timestamp_millis() as f64 / 1000.0)
+            .bind(to.timestamp_millis() as f64 / 1000.0);
+    }
+
+    let rows = query.fetch_all::<WordUsageRow>().await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| WordUsage {
+            phrase: row.phrase,
+            count: row.uses,
+        })
+        .collect())
+}
+
+pub async fn get_channel_activity(
+    db: &Client,
+    channel_id: &str,
+    interval: TimeseriesInterval,
+    (from, to): (DateTime<Utc>, DateTime<Utc>),
+) -> Result<Vec<ChannelActivityBucket>> {
+    #[derive(Deserialize, Row)]
+    struct ActivityRow {
+        time: u32,
+        messages: u64,
+        chatters: u64,
+        subs: u64,
+        raids: u64,
+        bans: u64,
+    }
+
+    let query = format!(
+        "SELECT
+            toDateTime(toStartOfInterval(bucket, {})) AS time,
+            sum(messages) AS messages,
+            uniqMerge(chatters) AS chatters,
+            sum(subs) AS subs,
+            sum(raids) AS raids,
+            sum(bans) AS bans
+        FROM channel_activity
+        WHERE channel_id = ? AND bucket >= toDateTime(?) AND bucket < toDateTime(?)
+        GROUP BY time
+        ORDER BY time ASC",
+        interval.sql_interval()
+    );
+
+    let rows = db
+        .query(&query)
+        .bind(channel_id)
+        .bind(from.timestamp())
+        .bind(to.timestamp())
+        .fetch_all::<ActivityRow>()
+        .await?;
+
+    let buckets = rows
+        .into_iter()
+        .map(|row| ChannelActivityBucket {
+            timestamp: DateTime::from_timestamp(row.time.into(), 0).expect("Invalid DateTime"),
+            message_count: row.messages,
+            chatter_count: row.chatters,
+            sub_count: row.subs,
+            raid_count: row.raids,
+            ban_count: row.bans,
+        })
+        .collect();
+
+    Ok(buckets)
+}
+
 pub async fn get_user_stats(
     db: &Client,
     channel_id: &str,
@@ -374,6 +937,47 @@ pub async fn get_user_stats(
     })
 }
 
+/// Reads the bans and timeouts of the user, along with the deletions of their messages
+pub async fn read_user_moderation(
+    db: &Client,
+    channel_id: &str,
+    user_id: &str,
+    range_params: LogRangeParams,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    let range_condition = if range_params.range().is_some() {
+        " AND timestamp >= ? AND timestamp < ?"
+    } else {
+        ""
+    };
+    let query = format!(
+        "SELECT * FROM message_structured WHERE channel_id = ? AND (
+            (user_id = ? AND message_type = {clearchat})
+            OR (user_id = '' AND message_type = {clearmsg} AND toUUIDOrZero(extra_tags['target-msg-id']) IN (SELECT id FROM message_structured WHERE channel_id = ? AND user_id = ?{range_condition}))
+        ){range_condition} ORDER BY timestamp ASC",
+        clearchat = MessageType::ClearChat as u8,
+        clearmsg = MessageType::ClearMsg as u8,
+    );
+
+    let mut query = db
+        .query(&query)
+        .bind(channel_id)
+        .bind(user_id)
+        .bind(channel_id)
+        .bind(user_id);
+
+    if let Some((from, to)) = range_params.range() {
+        // Deleted messages were sent at most the deletion window before their deletion
+        let deleted_from = from - Duration::hours(MESSAGE_DELETION_WINDOW_HOURS);
+        query = query
+            .bind(deleted_from.timestamp_millis() as f64 / 1000.0)
+            .bind(to.timestamp_millis() as f64 / 1000.0)
+            .bind(from.timestamp_millis() as f64 / 1000.0)
+            .bind(to.timestamp_millis() as f64 / 1000.0);
+    }
+
+    Ok(query.fetch_all().await?)
+}
+
 pub async fn get_user_name_history(db: &Client, user_id: &str) -> Result<Vec<PreviousName>> {
     #[derive(Deserialize, Row)]
     struct SingleNameHistory {
@@ -415,6 +1019,114 @@ pub async fn get_user_name_history(db: &Client, user_id: &str) -> Result<Vec<Pre
     Ok(names)
 }
 
+#[derive(Deserialize, Row)]
+struct DeletionRow {
+    #[serde(with = "clickhouse::serde::uuid")]
+    target_id: Uuid,
+    timestamp: u64,
+}
+
+/// Reads the deletions of channel messages sent in the given range
+pub async fn read_message_deletions(
+    db: &Client,
+    channel_id: &str,
+    range: (DateTime<Utc>, DateTime<Utc>),
+    flush_buffer: &FlushBuffer,
+) -> Result<MessageDeletions> {
+    let (from, to) = deletions_range(range);
+    let (from, to) = (from.timestamp_millis(), to.timestamp_millis());
+
+    let rows = db
+        .query("SELECT toUUIDOrZero(extra_tags['target-msg-id']) AS target_id, toUInt64(toUnixTimestamp64Milli(timestamp)) AS timestamp FROM message_structured WHERE channel_id = ? AND user_id = '' AND message_type = ? AND timestamp >= ? AND timestamp < ?")
+        .bind(channel_id)
+        .bind(MessageType::ClearMsg as u8)
+        .bind(from as f64 / 1000.0)
+        .bind(to as f64 / 1000.0)
+        .fetch_all::<DeletionRow>()
+        .await?;
+
+    let mut deletions = MessageDeletions::default();
+    for row in rows {
+        deletions.insert(row.target_id, row.timestamp);
+    }
+    deletions.extend_from_messages(
+        &flush_buffer
+            .messages_by_channel(from as u64..to as u64, channel_id)
+            .await,
+    );
+
+    Ok(deletions)
+}
+
+/// Condition for the `deleted` filter, with placeholders for the channel id and the range of the deletions added by `bind_deleted_filter`
+fn deleted_condition(filter: Option<DeletedFilter>) -> String {
+    let operator = match filter {
+        Some(DeletedFilter::Only) => "IN",
+        Some(DeletedFilter::Exclude) => "NOT IN",
+        None => return String::new(),
+    };
+    format!(
+        " AND id {operator} (SELECT toUUIDOrZero(extra_tags['target-msg-id']) FROM message_structured WHERE channel_id = ? AND user_id = '' AND message_type = {} AND timestamp >= ? AND timestamp < ?)",
+        MessageType::ClearMsg as u8
+    )
+}
+
+/// Binds the deletions which can target messages in the range
+fn bind_deleted_filter(
+    query: Query,
+    filter: Option<DeletedFilter>,
+    channel_id: &str,
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Query {
+    match filter {
+        Some(_) => {
+            let (from, to) = deletions_range(range);
+            query
+                .bind(channel_id)
+                .bind(from.timestamp_millis() as f64 / 1000.0)
+                .bind(to.timestamp_millis() as f64 / 1000.0)
+        }
+        None => query,
+    }
+}
+
+/// Time range of the deletions of messages sent in the given range
+fn deletions_range((from, to): (DateTime<Utc>, DateTime<Utc>)) -> (DateTime<Utc>, DateTime<Utc>) {
+    (from, to + Duration::hours(MESSAGE_DELETION_WINDOW_HOURS))
+}
+
+/// Filters out the messages up to the pagination cursor and orders the rest consistently with it
+fn pagination_clause(params: &LogsParams) -> String {
+    let suffix = if params.reverse { "DESC" } else { "ASC" };
+
+    match params.cursor {
+        Some(cursor) => format!(
+            " AND {} ORDER BY timestamp {suffix}, id {suffix}",
+            cursor.sql_condition(params.reverse)
+        ),
+        None => format!(" ORDER BY timestamp {suffix}, id {suffix}"),
+    }
+}
+
+/// Skips the part of the range that is before the pagination cursor
+pub fn narrow_range(
+    params: &LogsParams,
+    (from, to): (DateTime<Utc>, DateTime<Utc>),
+) -> (DateTime<Utc>, DateTime<Utc>) {
+    let Some(cursor_time) = params
+        .cursor
+        .and_then(|cursor| DateTime::from_timestamp_millis(cursor.timestamp as i64))
+    else {
+        return (from, to);
+    };
+
+    if params.reverse {
+        (from, to.min(cursor_time + Duration::milliseconds(1)))
+    } else {
+        (from.max(cursor_time), to)
+    }
+}
+
 fn apply_limit_offset(query: &mut String, buffer_response: &FlushBufferResponse) {
     if let Some(limit) = buffer_response.normalized_limit() {
         *query = format!("{query} LIMIT {limit}");
@@ -46,7 +46,7 @@ impl MessageFlags {
         Some(value)
     }
 
-    pub fn as_tags(&self) -> impl Iterator<Item = (Tag, &'static str)> {
+    pub fn as_tags(&self) -> impl Iterator<Item = (Tag<'_>, &'static str)> {
         [
             Tag::Subscriber,
             Tag::Vip,
@@ -282,6 +282,66 @@ impl<'a> StructuredMessage<'a> {
         }
     }
 
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.id
+    }
+
+    /// Identifies copies of the same message: the message id, or a hash of the channel, timestamp and raw message when there is none
+    pub fn dedupe_key(&self) -> Uuid {
+        if self.id.is_nil() {
+            let name = format!(
+                "{}\n{}\n{}",
+                self.channel_id,
+                self.timestamp,
+                self.to_raw_irc()
+            );
+            Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes())
+        } else {
+            self.id
+        }
+    }
+
+    fn extra_tag(&self, tag: Tag) -> Option<&str> {
+        self.extra_tags
+            .iter()
+            .find(|(name, _)| name == tag.as_str())
+            .map(|(_, value)| value.as_ref())
+    }
+
+    /// Id of the message removed by a `CLEARMSG`
+    pub fn target_message_id(&self) -> Option<Uuid> {
+        if self.message_type != MessageType::ClearMsg {
+            return None;
+        }
+
+        self.extra_tag(Tag::TargetMsgId)
+            .and_then(|value| Uuid::parse_str(value).ok())
+    }
+
+    /// Text of the message removed by a `CLEARMSG`
+    pub fn deleted_message_text(&self) -> Option<&str> {
+        if self.message_type != MessageType::ClearMsg {
+            return None;
+        }
+
+        let text = self.text.strip_prefix(':').unwrap_or(&self.text);
+        Some(extract_message_text(text))
+    }
+
+    /// Timeout duration in seconds of a `CLEARCHAT`, which is a ban when not set
+    pub fn ban_duration(&self) -> Option<u64> {
+        if self.message_type != MessageType::ClearChat {
+            return None;
+        }
+
+        self.extra_tag(Tag::BanDuration)
+            .and_then(|value| value.parse().ok())
+    }
+
     pub fn display_name(&self) -> &str {
         if !self.display_name.is_empty() {
             &self.display_name
@@ -290,7 +350,7 @@ impl<'a> StructuredMessage<'a> {
         }
     }
 
-    pub fn all_tags(&self, escape: bool) -> Vec<(Tag, Cow<'_, str>)> {
+    pub fn all_tags(&self, escape: bool) -> Vec<(Tag<'_>, Cow<'_, str>)> {
         let mut tags = Vec::with_capacity(16);
 
         tags.push((Tag::TmiSentTs, Cow::Owned(self.timestamp.to_string())));
@@ -451,6 +511,28 @@ impl<'a> StructuredMessage<'a> {
         out
     }
 
+    /// Approximate memory usage of the message
+    pub fn estimated_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.channel_id.len()
+            + self.channel_login.len()
+            + self.user_id.len()
+            + self.user_login.len()
+            + self.display_name.len()
+            + self.user_type.len()
+            + self.badges.iter().map(|badge| badge.len()).sum::<usize>()
+            + self.badge_info.len()
+            + self.client_nonce.len()
+            + self.emotes.len()
+            + self.automod_flags.len()
+            + self.text.len()
+            + self
+                .extra_tags
+                .iter()
+                .map(|(key, value)| key.len() + value.len())
+                .sum::<usize>()
+    }
+
     pub fn into_owned(self) -> StructuredMessage<'static> {
         StructuredMessage {
             channel_id: Cow::Owned(self.channel_id.into_owned()),
@@ -528,7 +610,7 @@ pub enum MessageType {
 }
 
 impl MessageType {
-    fn from_tmi_command(cmd: tmi::Command) -> Option<Self> {
+    pub fn from_tmi_command(cmd: tmi::Command) -> Option<Self> {
         use MessageType::*;
         let msg_type = match cmd {
             tmi::Command::Ping => Ping,
@@ -704,6 +786,45 @@ mod tests {
         assert_roundtrip(unstructured);
     }
 
+    #[test]
+    fn dedupe_key() {
+        let raw = "@returning-chatter=0;user-id=68136884;user-type=;badges=vip/1,subscriber/60;mod=0;display-name=Supibot;room-id=22484632;flags=;emotes=;first-msg=0;vip=1;tmi-sent-ts=1709251274940;id=272e342c-5864-4c59-b730-25908cdb7f57;subscriber=1;turbo=0;color=#1E90FF;badge-info=subscriber/65 :supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #forsen :+join 󠀀";
+        let unstructured = UnstructuredMessage {
+            channel_id: "22484632",
+            user_id: "68136884",
+            timestamp: 1709251274940,
+            raw,
+        };
+        let message = StructuredMessage::from_unstructured(&unstructured).unwrap();
+        assert_eq!(message.uuid(), message.dedupe_key());
+
+        let roomstate = |timestamp| UnstructuredMessage {
+            channel_id: "118353866",
+            user_id: "",
+            timestamp,
+            raw: r"@emote-only=0;followers-only=-1;slow=0;subs-only=0;room-id=118353866;r9k=0 :tmi.twitch.tv ROOMSTATE #twitchmedia_qs_1",
+        };
+        let first = roomstate(1686947117960);
+        let second = roomstate(1686947117961);
+        let first_key = StructuredMessage::from_unstructured(&first)
+            .unwrap()
+            .dedupe_key();
+
+        assert!(!first_key.is_nil());
+        assert_eq!(
+            first_key,
+            StructuredMessage::from_unstructured(&first)
+                .unwrap()
+                .dedupe_key()
+        );
+        assert_ne!(
+            first_key,
+            StructuredMessage::from_unstructured(&second)
+                .unwrap()
+                .dedupe_key()
+        );
+    }
+
     #[test]
     fn clearchar_to_raw() {
         let structured = StructuredMessage {
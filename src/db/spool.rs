@@ -0,0 +1,163 @@
+use super::schema::StructuredMessage;
+use anyhow::Context;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Append-only file which holds the messages of the flush buffer until they are written to the database,
+/// so they can be recovered after a crash
+pub struct Spool {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl Spool {
+    /// Opens the spool file, returning it along with the messages left over from the previous run
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<(Self, Vec<StructuredMessage<'static>>)> {
+        let path = path.as_ref().to_path_buf();
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Could not open spool file {path:?}"))?;
+
+        let mut messages = Vec::new();
+        for (i, line) in BufReader::new(&file).lines().enumerate() {
+            let line = line.with_context(|| format!("Could not read line {i} from spool"))?;
+            match serde_json::from_str::<StructuredMessage>(&line) {
+                Ok(msg) => messages.push(msg),
+                // The last line could have been partially written
+                Err(err) => warn!("Skipping invalid spool entry on line {i}: {err}"),
+            }
+        }
+
+        if !messages.is_empty() {
+            info!("Recovered {} messages from spool", messages.len());
+        }
+
+        let spool = Self {
+            path,
+            writer: BufWriter::new(file),
+        };
+        Ok((spool, messages))
+    }
+
+    pub fn append(&mut self, msg: &StructuredMessage) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, msg)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Removes and returns up to `count` of the oldest entries, along with the number of entries left
+    ///
+    /// The remaining entries are copied to a temporary file which then replaces the spool,
+    /// so a crash while draining does not lose them
+    pub fn drain(
+        &mut self,
+        count: usize,
+    ) -> anyhow::Result<(Vec<StructuredMessage<'static>>, usize)> {
+        self.writer.flush()?;
+
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut tmp_writer = BufWriter::new(
+            File::create(&tmp_path)
+                .with_context(|| format!("Could not create temporary spool file {tmp_path:?}"))?,
+        );
+        let mut messages = Vec::new();
+        let mut remaining_count = 0;
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.with_context(|| format!("Could not read line {i} from spool"))?;
+            if messages.len() < count {
+                match serde_json::from_str::<StructuredMessage>(&line) {
+                    Ok(msg) => messages.push(msg),
+                    Err(err) => warn!("Skipping invalid spool entry on line {i}: {err}"),
+                }
+            } else {
+                tmp_writer.write_all(line.as_bytes())?;
+                tmp_writer.write_all(b"\n")?;
+                remaining_count += 1;
+            }
+        }
+
+        tmp_writer.flush()?;
+        tmp_writer.get_ref().sync_all()?;
+        drop(tmp_writer);
+
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Could not replace spool file {:?}", self.path))?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("Could not open spool file {:?}", self.path))?;
+        self.writer = BufWriter::new(file);
+
+        Ok((messages, remaining_count))
+    }
+
+    /// Discards all entries, should be called after they were written to the database
+    pub fn truncate(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        self.writer
+            .get_ref()
+            .set_len(0)
+            .with_context(|| format!("Could not truncate spool file {:?}", self.path))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Spool;
+    use crate::db::schema::{StructuredMessage, UnstructuredMessage};
+    use pretty_assertions::assert_eq;
+    use std::fs;
+
+    #[test]
+    fn recover_messages() {
+        let path = std::env::temp_dir().join(format!("rustlog-spool-{}", uuid::Uuid::new_v4()));
+
+        let unstructured = UnstructuredMessage {
+            channel_id: "22484632",
+            user_id: "68136884",
+            timestamp: 1709251274940,
+            raw: r"@returning-chatter=0;user-id=68136884;user-type=;badges=vip/1,subscriber/60;mod=0;display-name=Supibot;room-id=22484632;flags=;emotes=;first-msg=0;vip=1;tmi-sent-ts=1709251274940;id=272e342c-5864-4c59-b730-25908cdb7f57;subscriber=1;turbo=0;color=#1E90FF;badge-info=subscriber/65;some-tag=escaped\svalue :supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #forsen :+join 󠀀",
+        };
+        let message = StructuredMessage::from_unstructured(&unstructured).unwrap();
+
+        let (mut spool, recovered) = Spool::open(&path).unwrap();
+        assert!(recovered.is_empty());
+        spool.append(&message).unwrap();
+        spool.append(&message).unwrap();
+        drop(spool);
+
+        let (mut spool, recovered) = Spool::open(&path).unwrap();
+        assert_eq!(vec![message.clone(), message.clone()], recovered);
+
+        let (drained, remaining_count) = spool.drain(1).unwrap();
+        assert_eq!(vec![message.clone()], drained);
+        assert_eq!(1, remaining_count);
+        spool.append(&message).unwrap();
+        drop(spool);
+
+        let (mut spool, recovered) = Spool::open(&path).unwrap();
+        assert_eq!(vec![message.clone(), message], recovered);
+
+        spool.truncate().unwrap();
+        drop(spool);
+
+        let (_, recovered) = Spool::open(&path).unwrap();
+        assert!(recovered.is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,10 +1,21 @@
-use super::schema::StructuredMessage;
-use crate::{db::schema::MESSAGES_STRUCTURED_TABLE, ShutdownRx};
+use super::{dedupe::find_stored_keys, schema::StructuredMessage, spool::Spool};
+use crate::{
+    config::{Config, OverflowPolicy},
+    db::schema::MESSAGES_STRUCTURED_TABLE,
+    logs::search::SearchQuery,
+    ShutdownRx,
+};
 use anyhow::{anyhow, Context};
 use clickhouse::Client;
+use dashmap::DashMap;
 use lazy_static::lazy_static;
-use prometheus::{register_int_gauge, IntGauge};
-use std::{ops::Range, sync::Arc, time::Duration};
+use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use std::{
+    collections::{HashSet, VecDeque},
+    ops::Range,
+    sync::Arc,
+    time::Duration,
+};
 use tokio::{
     sync::{
         mpsc::{channel, Sender},
@@ -13,10 +24,15 @@ use tokio::{
     task::JoinHandle,
     time::{sleep, Instant},
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
+use uuid::Uuid;
 
 const RETRY_COUNT: usize = 20;
 const RETRY_INTERVAL_SECONDS: u64 = 5;
+/// How many dropped messages can pile up at the start of the spool before it is rewritten without them
+const SPOOL_COMPACT_THRESHOLD: usize = 1000;
+/// Maximum number of messages moved from the spill file into the buffer at once
+const SPILL_LOAD_CHUNK_SIZE: usize = 10_000;
 
 lazy_static! {
     static ref BATCH_MSG_COUNT_GAGUE: IntGauge = register_int_gauge!(
@@ -24,11 +40,36 @@ lazy_static! {
         "How many messages are written to the database per batch"
     )
     .unwrap();
+    static ref BUFFER_MSG_COUNT_GAUGE: IntGauge = register_int_gauge!(
+        "rustlog_flush_buffer_messages",
+        "How many messages are waiting in the flush buffer"
+    )
+    .unwrap();
+    static ref BUFFER_BYTES_GAUGE: IntGauge = register_int_gauge!(
+        "rustlog_flush_buffer_bytes",
+        "Estimated size of the messages waiting in the flush buffer"
+    )
+    .unwrap();
+    static ref SPILLED_MSG_COUNT_GAUGE: IntGauge = register_int_gauge!(
+        "rustlog_flush_buffer_spilled_messages",
+        "How many messages are spilled to disk because the flush buffer is full"
+    )
+    .unwrap();
+    static ref DROPPED_MESSAGES_COUNTER: IntCounter = register_int_counter!(
+        "rustlog_flush_buffer_dropped_messages",
+        "How many messages were dropped because the flush buffer is full"
+    )
+    .unwrap();
+    static ref DUPLICATE_MESSAGES_COUNTER: IntCounter = register_int_counter!(
+        "rustlog_duplicate_messages",
+        "How many messages were not written because they were already stored"
+    )
+    .unwrap();
 }
 
 #[derive(Default, Clone)]
 pub struct FlushBuffer {
-    messages: Arc<RwLock<Vec<StructuredMessage<'static>>>>,
+    messages: Arc<RwLock<VecDeque<StructuredMessage<'static>>>>,
 }
 
 impl FlushBuffer {
@@ -50,6 +91,39 @@ impl FlushBuffer {
         msgs
     }
 
+    pub async fn search_messages_by_channel(
+        &self,
+        time_range: Range<u64>,
+        channel_id: &str,
+        search: &SearchQuery,
+    ) -> Vec<StructuredMessage<'static>> {
+        let msgs = self
+            .messages
+            .read()
+            .await
+            .iter()
+            .filter(|msg| time_range.contains(&msg.timestamp))
+            .filter(|msg| msg.channel_id == channel_id)
+            .filter(|msg| search.matches(msg))
+            .cloned()
+            .collect::<Vec<_>>();
+        trace!("Found {} matching messages in flush buffer", msgs.len());
+        msgs
+    }
+
+    pub async fn message_by_id(
+        &self,
+        channel_id: &str,
+        id: Uuid,
+    ) -> Option<StructuredMessage<'static>> {
+        self.messages
+            .read()
+            .await
+            .iter()
+            .find(|msg| msg.channel_id == channel_id && msg.uuid() == id)
+            .cloned()
+    }
+
     pub async fn messages_by_channel_and_user(
         &self,
         time_range: Range<u64>,
@@ -73,7 +147,7 @@ impl FlushBuffer {
 pub async fn create_writer(
     db: Client,
     mut shutdown_rx: ShutdownRx,
-    flush_interval: u64,
+    config: Arc<Config>,
 ) -> anyhow::Result<(
     Sender<StructuredMessage<'static>>,
     FlushBuffer,
@@ -82,42 +156,271 @@ pub async fn create_writer(
     let (tx, mut rx) = channel(1000);
 
     let flush_buffer = FlushBuffer::default();
-    let flush_buffer_clone = flush_buffer.clone();
+    let flush_interval = config.clickhouse_flush_interval;
+
+    let mut writer = Writer::new(db, flush_buffer.clone(), config).await?;
 
     let handle = tokio::spawn(async move {
+        if !writer.buffer.messages.read().await.is_empty() {
+            info!("Writing recovered messages from spool");
+            writer.flush().await;
+        }
+
         let timeout = tokio::time::sleep(Duration::from_secs(flush_interval));
         tokio::pin!(timeout);
 
         loop {
+            let accepts_messages = writer.accepts_messages().await;
+
             tokio::select! {
                 _ = &mut timeout => {
                     timeout.as_mut().reset(Instant::now() + Duration::from_secs(flush_interval));
-                    if let Err(err) = write_chunk_with_retry(&db, &flush_buffer).await {
-                        error!("Could not write messages: {err}");
-                    }
+                    writer.flush().await;
                 }
-                Some(msg) = rx.recv() => {
-                    flush_buffer.messages.write().await.push(msg);
+                Some(msg) = rx.recv(), if accepts_messages => {
+                    writer.push(msg).await;
                 }
                 Ok(()) = shutdown_rx.changed() => {
                     info!("Flushing database write buffer");
+                    writer.flush().await;
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok((tx, flush_buffer, handle))
+}
+
+struct Writer {
+    db: Client,
+    buffer: FlushBuffer,
+    config: Arc<Config>,
+    spool: Option<Spool>,
+    spill: Option<Spool>,
+    max_size: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    /// Estimated size of the messages in the buffer
+    buffer_bytes: usize,
+    /// How many messages are stored in the spill file
+    spilled_count: usize,
+    /// How many of the oldest spool entries belong to dropped messages
+    spool_dropped_count: usize,
+}
+
+impl Writer {
+    async fn new(db: Client, buffer: FlushBuffer, config: Arc<Config>) -> anyhow::Result<Self> {
+        let spool = match &config.writer_spool_path {
+            Some(path) => {
+                let (spool, recovered_messages) = Spool::open(path)?;
+                buffer.messages.write().await.extend(recovered_messages);
+                Some(spool)
+            }
+            None => None,
+        };
+
+        let mut spilled_count = 0;
+        let spill = match config.writer_buffer_overflow_policy {
+            OverflowPolicy::Spill => {
+                let path = config.writer_spill_path.as_ref().context(
+                    "`writerSpillPath` needs to be set to use the spill overflow policy",
+                )?;
+                let (spill, spilled_messages) = Spool::open(path)?;
+                spilled_count = spilled_messages.len();
+                Some(spill)
+            }
+            OverflowPolicy::Block | OverflowPolicy::DropOldest => None,
+        };
+
+        let max_size = config.writer_buffer_max_size;
+        let overflow_policy = config.writer_buffer_overflow_policy;
+        let mut writer = Self {
+            db,
+            buffer,
+            config,
+            spool,
+            spill,
+            max_size,
+            overflow_policy,
+            buffer_bytes: 0,
+            spilled_count,
+            spool_dropped_count: 0,
+        };
+        if writer.overflow_policy == OverflowPolicy::DropOldest {
+            writer.drop_excess_recovered().await?;
+        }
+        writer.update_buffer_metrics().await;
 
-                    if let Err(err) = write_chunk_with_retry(&db, &flush_buffer).await {
-                        error!("Could not flush messages: {err}");
+        Ok(writer)
+    }
+
+    async fn is_full(&self) -> bool {
+        match self.max_size {
+            Some(max_size) => self.buffer.messages.read().await.len() >= max_size,
+            None => false,
+        }
+    }
+
+    /// With the block policy, no new messages are received until the buffer is flushed,
+    /// which applies backpressure to the bot through the channel
+    async fn accepts_messages(&self) -> bool {
+        self.overflow_policy != OverflowPolicy::Block || !self.is_full().await
+    }
+
+    async fn push(&mut self, msg: StructuredMessage<'static>) {
+        if self.is_full().await || self.spilled_count > 0 {
+            match self.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    let mut messages = self.buffer.messages.write().await;
+                    if let Some(dropped) = messages.pop_front() {
+                        self.buffer_bytes -= dropped.estimated_size();
+                        DROPPED_MESSAGES_COUNTER.inc();
+                        drop(messages);
+
+                        if self.spool.is_some() {
+                            self.spool_dropped_count += 1;
+                            if self.spool_dropped_count >= SPOOL_COMPACT_THRESHOLD {
+                                self.compact_spool();
+                            }
+                        }
                     }
+                }
+                OverflowPolicy::Spill => {
+                    // Keep spilling while the spill file is not empty to preserve the message order
+                    let spill = self.spill.as_mut().expect("Spill file not opened");
+                    match spill.append(&msg) {
+                        Ok(()) => {
+                            self.spilled_count += 1;
+                            SPILLED_MSG_COUNT_GAUGE.set(self.spilled_count.try_into().unwrap());
+                            return;
+                        }
+                        Err(err) => {
+                            error!("Could not spill message to disk, dropping it: {err:#}");
+                            DROPPED_MESSAGES_COUNTER.inc();
+                            return;
+                        }
+                    }
+                }
+                OverflowPolicy::Block => (),
+            }
+        }
 
-                    break;
+        if let Some(spool) = &mut self.spool {
+            if let Err(err) = spool.append(&msg) {
+                error!("Could not append message to spool: {err:#}");
+            }
+        }
+
+        self.buffer_bytes += msg.estimated_size();
+        let mut messages = self.buffer.messages.write().await;
+        messages.push_back(msg);
+
+        BUFFER_MSG_COUNT_GAUGE.set(messages.len().try_into().unwrap());
+        BUFFER_BYTES_GAUGE.set(self.buffer_bytes.try_into().unwrap());
+    }
+
+    async fn flush(&mut self) {
+        match write_chunk_with_retry(&self.db, &self.buffer, &self.config.opt_out).await {
+            Ok(()) => {
+                if let Some(spool) = &mut self.spool {
+                    if let Err(err) = spool.truncate() {
+                        error!("Could not truncate spool: {err:#}");
+                    }
+                    self.spool_dropped_count = 0;
+                }
+
+                if self.spilled_count > 0 {
+                    if let Err(err) = self.load_spilled().await {
+                        error!("Could not load spilled messages: {err:#}");
+                    }
                 }
             }
+            Err(err) => {
+                error!("Could not write messages: {err}");
+                self.compact_spool();
+            }
         }
-    });
 
-    Ok((tx, flush_buffer_clone, handle))
+        self.update_buffer_metrics().await;
+    }
+
+    /// Removes the entries of dropped messages from the spool, so they are not recovered after a restart
+    fn compact_spool(&mut self) {
+        let Some(spool) = &mut self.spool else {
+            return;
+        };
+        if self.spool_dropped_count == 0 {
+            return;
+        }
+
+        match spool.drain(self.spool_dropped_count) {
+            Ok(_) => self.spool_dropped_count = 0,
+            Err(err) => error!("Could not remove dropped messages from spool: {err:#}"),
+        }
+    }
+
+    /// Drops the oldest recovered messages that do not fit into the buffer
+    async fn drop_excess_recovered(&mut self) -> anyhow::Result<()> {
+        let Some(max_size) = self.max_size else {
+            return Ok(());
+        };
+
+        let mut messages = self.buffer.messages.write().await;
+        let excess = messages.len().saturating_sub(max_size);
+        if excess > 0 {
+            messages.drain(..excess);
+            DROPPED_MESSAGES_COUNTER.inc_by(excess as u64);
+            if let Some(spool) = &mut self.spool {
+                spool.drain(excess)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves spilled messages back into the buffer, up to its maximum size
+    async fn load_spilled(&mut self) -> anyhow::Result<()> {
+        let spill = self.spill.as_mut().expect("Spill file not opened");
+
+        let mut messages = self.buffer.messages.write().await;
+        let free_space = match self.max_size {
+            Some(max_size) => max_size.saturating_sub(messages.len()),
+            None => SPILL_LOAD_CHUNK_SIZE,
+        };
+        let (loaded, remaining_count) = spill.drain(free_space.min(SPILL_LOAD_CHUNK_SIZE))?;
+
+        debug!("Loaded {} spilled messages", loaded.len());
+        self.spilled_count = remaining_count;
+
+        if let Some(spool) = &mut self.spool {
+            for msg in &loaded {
+                if let Err(err) = spool.append(msg) {
+                    error!("Could not append message to spool: {err:#}");
+                }
+            }
+        }
+        messages.extend(loaded);
+
+        Ok(())
+    }
+
+    async fn update_buffer_metrics(&mut self) {
+        let messages = self.buffer.messages.read().await;
+        self.buffer_bytes = messages.iter().map(StructuredMessage::estimated_size).sum();
+
+        BUFFER_MSG_COUNT_GAUGE.set(messages.len().try_into().unwrap());
+        BUFFER_BYTES_GAUGE.set(self.buffer_bytes.try_into().unwrap());
+        SPILLED_MSG_COUNT_GAUGE.set(self.spilled_count.try_into().unwrap());
+    }
 }
 
-async fn write_chunk_with_retry(db: &Client, buffer: &FlushBuffer) -> anyhow::Result<()> {
+async fn write_chunk_with_retry(
+    db: &Client,
+    buffer: &FlushBuffer,
+    opt_out: &DashMap<String, bool>,
+) -> anyhow::Result<()> {
     for attempt in 1..=RETRY_COUNT {
-        match write_chunk(db, buffer).await {
+        match write_chunk(db, buffer, opt_out).await {
             Ok(()) => {
                 if attempt > 1 {
                     debug!("Insert succeeded on attempt {attempt}");
@@ -135,14 +438,38 @@ async fn write_chunk_with_retry(db: &Client, buffer: &FlushBuffer) -> anyhow::Re
     ))
 }
 
-async fn write_chunk(db: &Client, buffer: &FlushBuffer) -> anyhow::Result<()> {
+/// Writes the buffered messages, except for the ones of users who opted out after they were buffered,
+/// as the deletion of their logs only applies to the messages stored before it
+async fn write_chunk(
+    db: &Client,
+    buffer: &FlushBuffer,
+    opt_out: &DashMap<String, bool>,
+) -> anyhow::Result<()> {
     let messages_read_guard = buffer.messages.read().await;
 
     let started_at = Instant::now();
 
+    // Messages replayed after a reconnect or logged by another instance.
+    // Failing to check for them should not block the messages from being written
+    let mut seen_keys = match find_stored_keys(db, messages_read_guard.iter()).await {
+        Ok(keys) => keys,
+        Err(err) => {
+            warn!("Could not check for duplicate messages, inserting them anyway: {err:#}");
+            HashSet::new()
+        }
+    };
+    let mut duplicate_count = 0;
+
     let mut insert = db.insert(MESSAGES_STRUCTURED_TABLE)?;
     for message in messages_read_guard.iter() {
-        insert.write(message).await.context("Could not write row")?;
+        if opt_out.contains_key(message.user_id.as_ref()) {
+            continue;
+        }
+        if seen_keys.insert(message.dedupe_key()) {
+            insert.write(message).await.context("Could not write row")?;
+        } else {
+            duplicate_count += 1;
+        }
     }
     drop(messages_read_guard);
 
@@ -154,6 +481,10 @@ async fn write_chunk(db: &Client, buffer: &FlushBuffer) -> anyhow::Result<()> {
         messages_write_guard.len(),
         started_at.elapsed().as_millis()
     );
+    if duplicate_count > 0 {
+        debug!("Skipped {duplicate_count} duplicate messages");
+        DUPLICATE_MESSAGES_COUNTER.inc_by(duplicate_count);
+    }
     BATCH_MSG_COUNT_GAGUE.set(messages_write_guard.len().try_into().unwrap());
     messages_write_guard.clear();
 
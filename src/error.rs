@@ -9,7 +9,7 @@ use twitch_api::helix::ClientRequestError;
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Twitch API error: {0}")]
-    Helix(#[from] ClientRequestError<reqwest::Error>),
+    Helix(#[from] Box<ClientRequestError<reqwest::Error>>),
     #[error("IO Error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Int parse error: {0}")]
@@ -45,6 +45,12 @@ impl IntoResponse for Error {
     }
 }
 
+impl From<ClientRequestError<reqwest::Error>> for Error {
+    fn from(err: ClientRequestError<reqwest::Error>) -> Self {
+        Self::Helix(Box::new(err))
+    }
+}
+
 impl From<anyhow::Error> for Error {
     fn from(err: anyhow::Error) -> Self {
         error!("Error: {err}");
@@ -0,0 +1,200 @@
+use crate::{
+    db::{read_channel_day, read_channel_log_days},
+    migrator::{
+        get_day_path,
+        reader::{COMPRESSED_CHANNEL_FILE, ZSTD_CHANNEL_FILE},
+    },
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+use tracing::{debug, info};
+
+const ZSTD_LEVEL: i32 = 10;
+
+#[derive(clap::ValueEnum, Serialize, Deserialize, JsonSchema, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveCompression {
+    /// `channel.txt.gz`, readable by justlog
+    #[default]
+    Gzip,
+    /// `channel.txt.zst`
+    Zstd,
+}
+
+impl ArchiveCompression {
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ArchiveCompression::Gzip => COMPRESSED_CHANNEL_FILE,
+            ArchiveCompression::Zstd => ZSTD_CHANNEL_FILE,
+        }
+    }
+}
+
+/// Writer of a day file, compressed unless it is a plain `channel.txt`
+pub enum ArchiveWriter {
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+    Plain(BufWriter<File>),
+}
+
+impl ArchiveWriter {
+    pub fn create(path: &Path, compression: ArchiveCompression) -> anyhow::Result<Self> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(match compression {
+            ArchiveCompression::Gzip => Self::Gzip(GzEncoder::new(file, Compression::default())),
+            ArchiveCompression::Zstd => Self::Zstd(zstd::Encoder::new(file, ZSTD_LEVEL)?),
+        })
+    }
+
+    pub fn create_plain(path: &Path) -> anyhow::Result<Self> {
+        Ok(Self::Plain(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let writer: &mut dyn Write = match self {
+            Self::Gzip(encoder) => encoder,
+            Self::Zstd(encoder) => encoder,
+            Self::Plain(file) => file,
+        };
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        let mut file = match self {
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Zstd(encoder) => encoder.finish()?,
+            Self::Plain(file) => file,
+        };
+        file.flush()
+    }
+}
+
+/// Writes the full history of a channel as a justlog directory tree
+/// (`channel_id/year/month/day/channel.txt.gz`), which can be imported again with `rustlog migrate`.
+///
+/// Messages of `excluded_user_ids` are left out, and `on_day_exported` is called with the number of messages after every finished day.
+pub async fn export_channel(
+    db: &clickhouse::Client,
+    channel_id: &str,
+    excluded_user_ids: &[String],
+    root_path: &Path,
+    compression: ArchiveCompression,
+    on_day_exported: impl FnMut(DateTime<Utc>, u64),
+) -> anyhow::Result<()> {
+    let days = read_channel_log_days(db, channel_id)
+        .await
+        .context("Could not get channel log dates")?;
+    info!(
+        "Exporting {} days of logs for channel {channel_id}",
+        days.len()
+    );
+
+    export_channel_days(
+        db,
+        channel_id,
+        excluded_user_ids,
+        &days,
+        root_path,
+        compression,
+        on_day_exported,
+    )
+    .await?;
+
+    info!("Exported channel {channel_id}");
+
+    Ok(())
+}
+
+/// Writes the given days of a channel into the directory tree, like `export_channel`
+pub async fn export_channel_days(
+    db: &clickhouse::Client,
+    channel_id: &str,
+    excluded_user_ids: &[String],
+    days: &[DateTime<Utc>],
+    root_path: &Path,
+    compression: ArchiveCompression,
+    mut on_day_exported: impl FnMut(DateTime<Utc>, u64),
+) -> anyhow::Result<()> {
+    for &day in days {
+        let day_path = get_day_path(root_path, channel_id, day);
+        fs::create_dir_all(&day_path)?;
+
+        let file_path = day_path.join(compression.file_name());
+        // Written under a temporary name first, so an interrupted export never leaves a truncated file behind
+        let tmp_path = file_path.with_extension("tmp");
+        debug!("Writing {file_path:?}");
+
+        let mut writer = ArchiveWriter::create(&tmp_path, compression)?;
+        let mut cursor = read_channel_day(db, channel_id, excluded_user_ids, day)?;
+        let mut count = 0;
+
+        while let Some(msg) = cursor.next().await? {
+            writer.write_line(&msg.to_raw_irc())?;
+            count += 1;
+        }
+
+        writer.finish()?;
+        fs::rename(&tmp_path, &file_path)?;
+
+        on_day_exported(day, count);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchiveCompression, ArchiveWriter};
+    use flate2::bufread::GzDecoder;
+    use pretty_assertions::assert_eq;
+    use std::{
+        fs::{self, File},
+        io::{BufRead, BufReader},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn archive_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("rustlog-export-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let lines = [
+            "@tmi-sent-ts=1 :a!a@a.tmi.twitch.tv PRIVMSG #b :c",
+            "second line",
+        ];
+
+        for compression in [ArchiveCompression::Gzip, ArchiveCompression::Zstd] {
+            let path = dir.join(compression.file_name());
+            let mut writer = ArchiveWriter::create(&path, compression).unwrap();
+            for line in lines {
+                writer.write_line(line).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let file = BufReader::new(File::open(&path).unwrap());
+            let read_lines: Vec<String> = match compression {
+                ArchiveCompression::Gzip => BufReader::new(GzDecoder::new(file))
+                    .lines()
+                    .collect::<Result<_, _>>()
+                    .unwrap(),
+                ArchiveCompression::Zstd => {
+                    BufReader::new(zstd::Decoder::with_buffer(file).unwrap())
+                        .lines()
+                        .collect::<Result<_, _>>()
+                        .unwrap()
+                }
+            };
+            assert_eq!(lines.to_vec(), read_lines);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
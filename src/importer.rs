@@ -0,0 +1,331 @@
+use crate::{
+    db::{
+        dedupe::find_stored_keys,
+        schema::{StructuredMessage, UnstructuredMessage, MESSAGES_STRUCTURED_TABLE},
+    },
+    logs::extract::{extract_channel_and_user_from_raw, extract_raw_timestamp},
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use clickhouse::inserter::Inserter;
+use dashmap::DashMap;
+use flate2::bufread::GzDecoder;
+use serde::{
+    de::{self, DeserializeSeed, IgnoredAny, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use std::{
+    collections::HashSet,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const INSERT_BATCH_SIZE: u64 = 1_000_000;
+/// How many messages are checked for duplicates at once
+const DEDUPE_BATCH_SIZE: usize = 10_000;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum ImportFormat {
+    /// One raw IRC message per line
+    Raw,
+    /// A `json` logs response
+    Json,
+    /// A `ndjson` logs response
+    Ndjson,
+}
+
+/// Message from a `json` or `ndjson` logs response. Only full messages have the `raw` field
+#[derive(Deserialize)]
+struct ExportedMessage {
+    raw: String,
+    timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Default, Debug)]
+pub struct ImportStats {
+    pub imported: u64,
+    pub skipped: u64,
+    pub duplicates: u64,
+}
+
+/// Imports a logs file, which can be compressed with gzip (`.gz`) or zstd (`.zst`).
+///
+/// Messages are assigned to the channel in their `room-id` tag, or to `default_channel_id` when it is missing.
+/// Messages which are already stored and messages of opted out users or channels are skipped.
+pub async fn import_file(
+    db: &clickhouse::Client,
+    path: &Path,
+    format: ImportFormat,
+    default_channel_id: Option<&str>,
+    opt_out: &DashMap<String, bool>,
+) -> anyhow::Result<ImportStats> {
+    let reader = open_file(path).with_context(|| format!("Could not open {path:?}"))?;
+
+    // The file is parsed on a blocking thread, so a `json` response does not have to be loaded into memory at once
+    let (message_tx, mut message_rx) = mpsc::channel(DEDUPE_BATCH_SIZE);
+    let read_handle =
+        tokio::task::spawn_blocking(move || read_messages(reader, format, message_tx));
+
+    let mut inserter = db
+        .inserter(MESSAGES_STRUCTURED_TABLE)?
+        .with_timeouts(
+            Some(Duration::from_secs(30)),
+            Some(Duration::from_secs(180)),
+        )
+        .with_max_rows(INSERT_BATCH_SIZE)
+        .with_period(Some(Duration::from_secs(15)));
+    let mut stats = ImportStats::default();
+    let mut seen_keys = HashSet::new();
+    let mut batch = Vec::with_capacity(DEDUPE_BATCH_SIZE);
+
+    while let Some(message) = message_rx.recv().await {
+        match parse_message(&message.raw, message.timestamp, default_channel_id) {
+            Some(msg)
+                if opt_out.contains_key(msg.channel_id.as_ref())
+                    || opt_out.contains_key(msg.user_id.as_ref()) =>
+            {
+                stats.skipped += 1;
+            }
+            Some(msg) => batch.push(msg),
+            None => stats.skipped += 1,
+        }
+
+        if batch.len() >= DEDUPE_BATCH_SIZE {
+            write_batch(db, &mut batch, &mut seen_keys, &mut inserter, &mut stats).await?;
+        }
+    }
+    read_handle
+        .await?
+        .context("Could not read file, full messages with the `raw` field are required")?;
+
+    write_batch(db, &mut batch, &mut seen_keys, &mut inserter, &mut stats).await?;
+    inserter.end().await.context("Could not flush messages")?;
+    info!(
+        "Imported {} messages from {path:?}, skipped {} and {} duplicates",
+        stats.imported, stats.skipped, stats.duplicates
+    );
+
+    Ok(stats)
+}
+
+fn open_file(path: &Path) -> anyhow::Result<Box<dyn BufRead + Send>> {
+    let file_reader = BufReader::new(File::open(path)?);
+
+    Ok(
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Box::new(BufReader::new(GzDecoder::new(file_reader))),
+            Some("zst") => Box::new(BufReader::new(zstd::Decoder::with_buffer(file_reader)?)),
+            _ => Box::new(file_reader),
+        },
+    )
+}
+
+/// Sends the messages of the file over the channel, stops early when the receiver is gone
+fn read_messages(
+    reader: Box<dyn BufRead + Send>,
+    format: ImportFormat,
+    message_tx: mpsc::Sender<ExportedMessage>,
+) -> anyhow::Result<()> {
+    match format {
+        ImportFormat::Raw | ImportFormat::Ndjson => {
+            for (i, line) in reader.lines().enumerate() {
+                let line = line.with_context(|| format!("Could not read line {i}"))?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let message = match format {
+                    ImportFormat::Ndjson => serde_json::from_str(&line)
+                        .with_context(|| format!("Could not parse line {i}"))?,
+                    _ => ExportedMessage {
+                        raw: line,
+                        timestamp: None,
+                    },
+                };
+                if message_tx.blocking_send(message).is_err() {
+                    break;
+                }
+            }
+        }
+        ImportFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_reader(reader);
+            deserializer.deserialize_map(JsonExportVisitor(&message_tx))?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends the entries of the `messages` array of a `json` logs response one by one
+struct JsonExportVisitor<'a>(&'a mpsc::Sender<ExportedMessage>);
+
+impl<'de> Visitor<'de> for JsonExportVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an object with a `messages` array")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "messages" {
+                map.next_value_seed(MessagesVisitor(self.0))?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct MessagesVisitor<'a>(&'a mpsc::Sender<ExportedMessage>);
+
+impl<'de> DeserializeSeed<'de> for MessagesVisitor<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for MessagesVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of messages")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(message) = seq.next_element::<ExportedMessage>()? {
+            self.0
+                .blocking_send(message)
+                .map_err(|_| de::Error::custom("import was stopped"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the messages which are neither stored yet nor were imported before in this run, and empties the batch
+async fn write_batch(
+    db: &clickhouse::Client,
+    batch: &mut Vec<StructuredMessage<'static>>,
+    seen_keys: &mut HashSet<Uuid>,
+    inserter: &mut Inserter<StructuredMessage<'static>>,
+    stats: &mut ImportStats,
+) -> anyhow::Result<()> {
+    seen_keys.extend(find_stored_keys(db, batch.iter()).await?);
+
+    for msg in batch.drain(..) {
+        if seen_keys.insert(msg.dedupe_key()) {
+            inserter.write(&msg)?;
+            stats.imported += 1;
+        } else {
+            stats.duplicates += 1;
+        }
+    }
+    inserter.commit().await?;
+
+    Ok(())
+}
+
+fn parse_message(
+    raw: &str,
+    fallback_timestamp: Option<DateTime<Utc>>,
+    default_channel_id: Option<&str>,
+) -> Option<StructuredMessage<'static>> {
+    let Some(irc_message) = tmi::IrcMessageRef::parse(raw.trim()) else {
+        warn!("Could not parse message `{raw}`");
+        return None;
+    };
+
+    let (channel_id, user_id) = match extract_channel_and_user_from_raw(&irc_message) {
+        Some((channel_id, user_id)) => (channel_id, user_id),
+        None => match default_channel_id {
+            Some(channel_id) => (channel_id, None),
+            None => {
+                warn!("Skipping message without a channel id: `{raw}`");
+                return None;
+            }
+        },
+    };
+
+    let Some(timestamp) = extract_raw_timestamp(&irc_message)
+        .or_else(|| fallback_timestamp.map(|timestamp| timestamp.timestamp_millis() as u64))
+    else {
+        warn!("Skipping message without a timestamp: `{raw}`");
+        return None;
+    };
+
+    let unstructured = UnstructuredMessage {
+        channel_id,
+        user_id: user_id.unwrap_or_default(),
+        timestamp,
+        raw: irc_message.raw(),
+    };
+    match StructuredMessage::from_unstructured(&unstructured) {
+        Ok(msg) => Some(msg.into_owned()),
+        Err(err) => {
+            warn!("Could not convert message {unstructured:?}: {err}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_message, read_messages, ExportedMessage, ImportFormat};
+    use chrono::DateTime;
+    use pretty_assertions::assert_eq;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn parse_exported_messages() {
+        let line = r#"{"text":"+join 󠀀","displayName":"Supibot","timestamp":"2024-03-01T00:01:14.94Z","id":"272e342c-5864-4c59-b730-25908cdb7f57","username":"supibot","channel":"forsen","raw":"@room-id=22484632;tmi-sent-ts=1709251274940;user-id=68136884;id=272e342c-5864-4c59-b730-25908cdb7f57 :supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #forsen :+join 󠀀","type":1}"#;
+        let exported: ExportedMessage = serde_json::from_str(line).unwrap();
+
+        let msg = parse_message(&exported.raw, exported.timestamp, None).unwrap();
+        assert_eq!("22484632", msg.channel_id);
+        assert_eq!("68136884", msg.user_id);
+        assert_eq!(1709251274940, msg.timestamp);
+
+        // Raw dumps without tags need a default channel and a timestamp from elsewhere
+        let raw = ":supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #forsen :hello";
+        assert!(parse_message(raw, None, Some("22484632")).is_none());
+        let msg = parse_message(
+            raw,
+            DateTime::from_timestamp_millis(1709251274940),
+            Some("22484632"),
+        )
+        .unwrap();
+        assert_eq!("22484632", msg.channel_id);
+        assert_eq!("hello", msg.text());
+    }
+
+    #[test]
+    fn read_json_response() {
+        let response = r#"{"messages":[{"raw":"first","timestamp":"2024-03-01T00:01:14.94Z"},{"text":"","raw":"second"}],"other":[1]}"#;
+        let (message_tx, mut message_rx) = mpsc::channel(10);
+
+        read_messages(
+            Box::new(response.as_bytes()),
+            ImportFormat::Json,
+            message_tx,
+        )
+        .unwrap();
+
+        let first = message_rx.try_recv().unwrap();
+        assert_eq!("first", first.raw);
+        assert_eq!(
+            DateTime::from_timestamp_millis(1709251274940),
+            first.timestamp
+        );
+        let second = message_rx.try_recv().unwrap();
+        assert_eq!("second", second.raw);
+        assert_eq!(None, second.timestamp);
+        assert!(message_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,84 @@
+use crate::db::schema::StructuredMessage;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Messages removed by moderators, keyed by the id of the deleted message
+#[derive(Default, Debug)]
+pub struct MessageDeletions {
+    deletions: HashMap<Uuid, MessageDeletion>,
+}
+
+/// Twitch chat does not say which moderator deleted a message, only whose message it was
+#[derive(Serialize, JsonSchema, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDeletion {
+    pub deleted_at: DateTime<Utc>,
+}
+
+impl MessageDeletions {
+    pub fn insert(&mut self, target_id: Uuid, timestamp: u64) {
+        let Some(deleted_at) = DateTime::from_timestamp_millis(timestamp as i64) else {
+            return;
+        };
+        self.deletions
+            .entry(target_id)
+            .or_insert(MessageDeletion { deleted_at });
+    }
+
+    /// Adds the deletions from the given `CLEARMSG` messages
+    pub fn extend_from_messages<'a>(
+        &mut self,
+        messages: impl IntoIterator<Item = &'a StructuredMessage<'a>>,
+    ) {
+        for msg in messages {
+            if let Some(target_id) = msg.target_message_id() {
+                self.insert(target_id, msg.timestamp);
+            }
+        }
+    }
+
+    pub fn get(&self, msg: &StructuredMessage) -> Option<MessageDeletion> {
+        self.deletions.get(&msg.uuid()).copied()
+    }
+
+    pub fn contains(&self, msg: &StructuredMessage) -> bool {
+        self.deletions.contains_key(&msg.uuid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MessageDeletions;
+    use crate::db::schema::{StructuredMessage, UnstructuredMessage};
+    use chrono::DateTime;
+
+    #[test]
+    fn link_clearmsg_to_message() {
+        let privmsg = UnstructuredMessage {
+            channel_id: "11148817",
+            user_id: "82008718",
+            timestamp: 1594561950000,
+            raw: "@badge-info=;badges=;color=#FF0000;display-name=alazymeme;emotes=;flags=;id=3c92014f-340a-4dc3-a9c9-e5cf182f4a84;mod=0;room-id=11148817;subscriber=0;tmi-sent-ts=1594561950000;turbo=0;user-id=82008718;user-type= :alazymeme!alazymeme@alazymeme.tmi.twitch.tv PRIVMSG #pajlada :lole",
+        };
+        let clearmsg = UnstructuredMessage {
+            channel_id: "11148817",
+            user_id: "",
+            timestamp: 1594561955611,
+            raw: "@login=alazymeme;room-id=11148817;target-msg-id=3c92014f-340a-4dc3-a9c9-e5cf182f4a84;tmi-sent-ts=1594561955611 :tmi.twitch.tv CLEARMSG #pajlada :lole",
+        };
+        let privmsg = StructuredMessage::from_unstructured(&privmsg).unwrap();
+        let clearmsg = StructuredMessage::from_unstructured(&clearmsg).unwrap();
+
+        let mut deletions = MessageDeletions::default();
+        deletions.extend_from_messages([&privmsg, &clearmsg]);
+
+        assert!(!deletions.contains(&clearmsg));
+        assert_eq!(
+            DateTime::from_timestamp_millis(1594561955611),
+            deletions.get(&privmsg).map(|deletion| deletion.deleted_at)
+        );
+    }
+}
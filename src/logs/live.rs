@@ -0,0 +1,92 @@
+use crate::{app::App, db::schema::StructuredMessage, ShutdownRx};
+use futures::{stream::BoxStream, Stream, StreamExt};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tracing::{debug, warn};
+
+pub type LiveMessage = Arc<StructuredMessage<'static>>;
+
+/// Follows the messages received by the bot as they are handed to the writer
+pub struct LiveLogsStream {
+    inner: BoxStream<'static, LiveMessage>,
+}
+
+struct LiveState {
+    app: App,
+    receiver: Receiver<LiveMessage>,
+    shutdown_rx: ShutdownRx,
+    channel_id: String,
+    user_id: Option<String>,
+}
+
+impl LiveState {
+    fn matches(&self, msg: &StructuredMessage) -> bool {
+        if msg.channel_id != self.channel_id {
+            return false;
+        }
+
+        if let Some(user_id) = &self.user_id {
+            if msg.user_id != *user_id {
+                return false;
+            }
+        }
+
+        let user_id = Some(msg.user_id.as_ref()).filter(|user_id| !user_id.is_empty());
+        self.app.check_opted_out(&msg.channel_id, user_id).is_ok()
+    }
+}
+
+impl LiveLogsStream {
+    pub fn new(
+        app: App,
+        channel_id: String,
+        user_id: Option<String>,
+        shutdown_rx: ShutdownRx,
+    ) -> Self {
+        let state = LiveState {
+            receiver: app.live_messages.subscribe(),
+            app,
+            shutdown_rx,
+            channel_id,
+            user_id,
+        };
+
+        let inner = futures::stream::unfold(state, |mut state| async move {
+            loop {
+                tokio::select! {
+                    result = state.receiver.recv() => match result {
+                        Ok(msg) => {
+                            if state.matches(&msg) {
+                                return Some((msg, state));
+                            }
+                        }
+                        Err(RecvError::Lagged(count)) => {
+                            warn!("Live logs subscriber for channel {} skipped {count} messages", state.channel_id);
+                        }
+                        Err(RecvError::Closed) => return None,
+                    },
+                    _ = state.shutdown_rx.changed() => {
+                        debug!("Closing live logs stream");
+                        return None;
+                    }
+                }
+            }
+        });
+
+        Self {
+            inner: inner.boxed(),
+        }
+    }
+}
+
+impl Stream for LiveLogsStream {
+    type Item = LiveMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
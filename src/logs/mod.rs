@@ -1,3 +1,8 @@
+pub mod deletions;
 pub mod extract;
+pub mod live;
+pub mod modlog;
+pub mod pagination;
 pub mod schema;
+pub mod search;
 pub mod stream;
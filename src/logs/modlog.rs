@@ -0,0 +1,140 @@
+use crate::db::schema::{MessageType, StructuredMessage};
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// Moderation actions taken against a user in a channel
+#[derive(Serialize, JsonSchema, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct UserModLog {
+    pub ban_count: u64,
+    pub timeout_count: u64,
+    /// Sum of all timeout durations in seconds
+    pub total_timeout_seconds: u64,
+    pub deleted_message_count: u64,
+    /// Oldest first
+    pub events: Vec<ModLogEvent>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ModLogEvent {
+    #[serde(rename_all = "camelCase")]
+    Ban { timestamp: DateTime<Utc> },
+    #[serde(rename_all = "camelCase")]
+    Timeout {
+        timestamp: DateTime<Utc>,
+        duration_seconds: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    Deletion {
+        timestamp: DateTime<Utc>,
+        message_id: Option<String>,
+        text: String,
+    },
+}
+
+impl UserModLog {
+    /// Builds the log from the user's `CLEARCHAT` messages and the `CLEARMSG` messages deleting the user's messages
+    pub fn from_messages(messages: &[StructuredMessage]) -> Self {
+        let mut modlog = Self::default();
+
+        for msg in messages {
+            let Some(timestamp) = DateTime::from_timestamp_millis(msg.timestamp as i64) else {
+                continue;
+            };
+
+            let event = match msg.message_type {
+                MessageType::ClearChat => match msg.ban_duration() {
+                    Some(duration_seconds) => {
+                        modlog.timeout_count += 1;
+                        modlog.total_timeout_seconds += duration_seconds;
+                        ModLogEvent::Timeout {
+                            timestamp,
+                            duration_seconds,
+                        }
+                    }
+                    None => {
+                        modlog.ban_count += 1;
+                        ModLogEvent::Ban { timestamp }
+                    }
+                },
+                MessageType::ClearMsg => {
+                    modlog.deleted_message_count += 1;
+                    ModLogEvent::Deletion {
+                        timestamp,
+                        message_id: msg.target_message_id().map(|id| id.to_string()),
+                        text: msg.deleted_message_text().unwrap_or_default().to_owned(),
+                    }
+                }
+                _ => continue,
+            };
+            modlog.events.push(event);
+        }
+
+        modlog
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ModLogEvent, UserModLog};
+    use crate::db::schema::{StructuredMessage, UnstructuredMessage};
+    use chrono::DateTime;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn build_modlog() {
+        let raw_messages = [
+            (
+                1594561950000,
+                "@ban-duration=600;room-id=11148817;target-user-id=82008718;tmi-sent-ts=1594561950000 :tmi.twitch.tv CLEARCHAT #pajlada :alazymeme",
+            ),
+            (
+                1594561955611,
+                "@login=alazymeme;room-id=11148817;target-msg-id=3c92014f-340a-4dc3-a9c9-e5cf182f4a84;tmi-sent-ts=1594561955611 :tmi.twitch.tv CLEARMSG #pajlada :\u{0001}ACTION lole\u{0001}",
+            ),
+            (
+                1594561960000,
+                "@room-id=11148817;target-user-id=82008718;tmi-sent-ts=1594561960000 :tmi.twitch.tv CLEARCHAT #pajlada :alazymeme",
+            ),
+        ];
+        let unstructured: Vec<_> = raw_messages
+            .into_iter()
+            .map(|(timestamp, raw)| UnstructuredMessage {
+                channel_id: "11148817",
+                user_id: "82008718",
+                timestamp,
+                raw,
+            })
+            .collect();
+        let messages: Vec<_> = unstructured
+            .iter()
+            .map(|msg| StructuredMessage::from_unstructured(msg).unwrap())
+            .collect();
+
+        let modlog = UserModLog::from_messages(&messages);
+
+        assert_eq!(1, modlog.ban_count);
+        assert_eq!(1, modlog.timeout_count);
+        assert_eq!(600, modlog.total_timeout_seconds);
+        assert_eq!(1, modlog.deleted_message_count);
+        assert_eq!(
+            vec![
+                ModLogEvent::Timeout {
+                    timestamp: DateTime::from_timestamp_millis(1594561950000).unwrap(),
+                    duration_seconds: 600,
+                },
+                ModLogEvent::Deletion {
+                    timestamp: DateTime::from_timestamp_millis(1594561955611).unwrap(),
+                    message_id: Some("3c92014f-340a-4dc3-a9c9-e5cf182f4a84".to_owned()),
+                    text: "lole".to_owned(),
+                },
+                ModLogEvent::Ban {
+                    timestamp: DateTime::from_timestamp_millis(1594561960000).unwrap(),
+                },
+            ],
+            modlog.events
+        );
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{db::schema::StructuredMessage, error::Error};
+use serde::{Deserialize, Deserializer};
+use std::{cmp::Ordering, fmt, str::FromStr};
+use uuid::Uuid;
+
+const ID_LENGTH: usize = 32;
+
+/// Position of a message in the logs, used for keyset pagination.
+///
+/// Serialized as an opaque string, clients should only pass back what they received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageCursor {
+    pub timestamp: u64,
+    pub id: Uuid,
+}
+
+impl PageCursor {
+    pub fn from_message(msg: &StructuredMessage) -> Self {
+        Self {
+            timestamp: msg.timestamp,
+            id: msg.uuid(),
+        }
+    }
+
+    /// Whether the message comes after the cursor in the given direction
+    pub fn is_followed_by(&self, msg: &StructuredMessage, reverse: bool) -> bool {
+        let ordering = (msg.timestamp, msg.uuid()).cmp(&(self.timestamp, self.id));
+        if reverse {
+            ordering == Ordering::Less
+        } else {
+            ordering == Ordering::Greater
+        }
+    }
+
+    /// SQL condition for `message_structured` selecting the messages after the cursor
+    pub fn sql_condition(&self, reverse: bool) -> String {
+        let operator = if reverse { "<" } else { ">" };
+        format!(
+            "(timestamp, id) {operator} (fromUnixTimestamp64Milli({}), toUUID('{}'))",
+            self.timestamp,
+            self.id.hyphenated()
+        )
+    }
+}
+
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}{}", self.timestamp, self.id.simple())
+    }
+}
+
+impl FromStr for PageCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidParam("Invalid cursor".to_owned());
+
+        if s.len() <= ID_LENGTH || !s.is_ascii() {
+            return Err(invalid());
+        }
+        let (timestamp, id) = s.split_at(s.len() - ID_LENGTH);
+
+        Ok(Self {
+            timestamp: u64::from_str_radix(timestamp, 16).map_err(|_| invalid())?,
+            id: Uuid::try_parse(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for PageCursor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PageCursor;
+    use pretty_assertions::assert_eq;
+    use uuid::Uuid;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let cursor = PageCursor {
+            timestamp: 1709251274940,
+            id: Uuid::parse_str("272e342c-5864-4c59-b730-25908cdb7f57").unwrap(),
+        };
+
+        let encoded = cursor.to_string();
+        assert_eq!("18df750a8bc272e342c58644c59b73025908cdb7f57", encoded);
+        assert_eq!(cursor, encoded.parse().unwrap());
+
+        assert!("".parse::<PageCursor>().is_err());
+        assert!("272e342c58644c59b73025908cdb7f57"
+            .parse::<PageCursor>()
+            .is_err());
+        assert!("zz272e342c58644c59b73025908cdb7f57"
+            .parse::<PageCursor>()
+            .is_err());
+    }
+}
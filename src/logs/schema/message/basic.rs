@@ -4,7 +4,7 @@ use schemars::JsonSchema;
 use serde::Serialize;
 use std::{borrow::Cow, collections::HashMap};
 
-use crate::db::schema::StructuredMessage;
+use crate::{db::schema::StructuredMessage, logs::deletions::MessageDeletion};
 
 use super::ResponseMessage;
 
@@ -16,6 +16,9 @@ pub struct BasicMessage<'a> {
     pub timestamp: DateTime<Utc>,
     pub id: Cow<'a, str>,
     pub tags: HashMap<&'a str, Cow<'a, str>>,
+    /// Only set when requested with `markDeleted`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted: Option<MessageDeletion>,
 }
 
 impl<'a> ResponseMessage<'a> for BasicMessage<'a> {
@@ -31,8 +34,13 @@ impl<'a> ResponseMessage<'a> for BasicMessage<'a> {
                 .into_iter()
                 .map(|(tag, value)| (tag.as_str(), value))
                 .collect(),
+            deleted: None,
         })
     }
+
+    fn mark_deleted(&mut self, deletion: MessageDeletion) {
+        self.deleted = Some(deletion);
+    }
 }
 
 #[cfg(test)]
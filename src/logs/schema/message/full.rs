@@ -1,5 +1,8 @@
 use super::{BasicMessage, ResponseMessage};
-use crate::db::schema::{MessageType, StructuredMessage};
+use crate::{
+    db::schema::{MessageType, StructuredMessage},
+    logs::deletions::MessageDeletion,
+};
 use schemars::JsonSchema;
 use serde::Serialize;
 
@@ -26,6 +29,10 @@ impl<'a> ResponseMessage<'a> for FullMessage<'a> {
             r#type: msg.message_type,
         })
     }
+
+    fn mark_deleted(&mut self, deletion: MessageDeletion) {
+        self.basic.mark_deleted(deletion);
+    }
 }
 
 #[cfg(test)]
@@ -74,6 +81,7 @@ mod tests {
                 .into_iter()
                 .map(|(k, v)| (k, Cow::Borrowed(v)))
                 .collect(),
+                deleted: None,
             },
             raw: "@tmi-sent-ts=1489263601000;room-id=22484632;user-id=62541963;display-name=Snusbot;badges=;badge-info=;flags=;user-type=;emotes= :snusbot!snusbot@snusbot.tmi.twitch.tv PRIVMSG #forsen :prasoc won 10 points in roulette and now has 2838 points! forsenPls".to_owned(),
             r#type: MessageType::PrivMsg,
@@ -6,8 +6,10 @@ pub use full::FullMessage;
 
 use serde::Serialize;
 
-use crate::db::schema::StructuredMessage;
+use crate::{db::schema::StructuredMessage, logs::deletions::MessageDeletion};
 
 pub trait ResponseMessage<'a>: Sized + Send + Serialize + Unpin {
     fn from_structured(msg: &'a StructuredMessage<'a>) -> anyhow::Result<Self>;
+
+    fn mark_deleted(&mut self, deletion: MessageDeletion);
 }
@@ -0,0 +1,344 @@
+use crate::{
+    db::schema::{MessageFlags, MessageType, StructuredMessage},
+    error::Error,
+    Result,
+};
+use clickhouse::query::Query;
+use regex::Regex;
+use std::{fmt::Write, iter::Peekable, str::Chars, str::FromStr};
+
+/// A parsed search query.
+///
+/// Terms separated by whitespace must all match, while `OR` separates alternative groups of terms.
+/// Supported terms:
+/// - `word` and `"quoted phrase"`: case-insensitive substring of the message text
+/// - `/regex/`: regular expression matched against the message text
+/// - `user:login`, `userid:id`: message author
+/// - `type:usernotice`: message type
+/// - `badge:moderator`: badge name, or the exact badge with its version (`badge:subscriber/12`)
+/// - `flag:first_msg`: message flag
+///
+/// Any term can be prefixed with `-` to exclude it.
+#[derive(Debug)]
+pub struct SearchQuery {
+    groups: Vec<Vec<SearchTerm>>,
+}
+
+#[derive(Debug)]
+struct SearchTerm {
+    negated: bool,
+    filter: SearchFilter,
+}
+
+#[derive(Debug)]
+enum SearchFilter {
+    Text(String),
+    Regex(Regex),
+    UserLogin(String),
+    UserId(String),
+    Type(MessageType),
+    Badge(String),
+    Flag(MessageFlags),
+}
+
+impl SearchQuery {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut groups = vec![];
+        let mut current_group = vec![];
+
+        let mut chars = input.chars().peekable();
+
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+            let Some(&c) = chars.peek() else {
+                break;
+            };
+
+            let negated = c == '-';
+            if negated {
+                chars.next();
+            }
+
+            let filter = match chars.peek() {
+                Some('"') => {
+                    chars.next();
+                    let phrase = read_until(&mut chars, '"');
+                    SearchFilter::Text(phrase)
+                }
+                Some('/') => {
+                    chars.next();
+                    let pattern = read_until(&mut chars, '/');
+                    let regex = Regex::new(&pattern)
+                        .map_err(|err| Error::InvalidParam(format!("Invalid regex: {err}")))?;
+                    SearchFilter::Regex(regex)
+                }
+                _ => {
+                    let mut word = String::new();
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                        word.push(c);
+                    }
+
+                    if word == "OR" && !negated {
+                        if !current_group.is_empty() {
+                            groups.push(std::mem::take(&mut current_group));
+                        }
+                        continue;
+                    }
+
+                    parse_word(word)?
+                }
+            };
+
+            if !matches!(&filter, SearchFilter::Text(text) if text.is_empty()) {
+                current_group.push(SearchTerm { negated, filter });
+            }
+        }
+
+        if !current_group.is_empty() {
+            groups.push(current_group);
+        }
+
+        if groups.is_empty() {
+            return Err(Error::InvalidParam("Empty search query".to_owned()));
+        }
+
+        Ok(Self { groups })
+    }
+
+    /// Compiles the regexes with the database's regex engine, which accepts a different syntax than the `regex` crate
+    pub async fn check_regexes(&self, db: &clickhouse::Client) -> Result<()> {
+        for term in self.groups.iter().flatten() {
+            if let SearchFilter::Regex(regex) = &term.filter {
+                db.query("SELECT match('', ?)")
+                    .bind(regex.as_str())
+                    .fetch_one::<u8>()
+                    .await
+                    .map_err(|err| match err {
+                        clickhouse::error::Error::BadResponse(msg) => {
+                            Error::InvalidParam(format!("Invalid regex: {msg}"))
+                        }
+                        err => Error::Clickhouse(err),
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// SQL condition for `message_structured`, with placeholders for the values added by `bind`
+    pub fn sql_condition(&self) -> String {
+        let mut out = String::from("(");
+
+        for (i, group) in self.groups.iter().enumerate() {
+            if i > 0 {
+                out.push_str(" OR ");
+            }
+            out.push('(');
+
+            for (j, term) in group.iter().enumerate() {
+                if j > 0 {
+                    out.push_str(" AND ");
+                }
+                if term.negated {
+                    out.push_str("NOT ");
+                }
+
+                let _ = match &term.filter {
+                    SearchFilter::Text(_) => {
+                        write!(out, "positionCaseInsensitiveUTF8(text, ?) != 0")
+                    }
+                    SearchFilter::Regex(_) => write!(out, "match(text, ?)"),
+                    SearchFilter::UserLogin(_) => write!(out, "user_login = ?"),
+                    SearchFilter::UserId(_) => write!(out, "user_id = ?"),
+                    SearchFilter::Type(message_type) => {
+                        write!(out, "message_type = {}", *message_type as u8)
+                    }
+                    SearchFilter::Badge(badge) => {
+                        if badge.contains('/') {
+                            write!(out, "has(badges, ?)")
+                        } else {
+                            write!(out, "arrayExists(badge -> startsWith(badge, ?), badges)")
+                        }
+                    }
+                    SearchFilter::Flag(flag) => {
+                        write!(out, "bitAnd(message_flags, {}) != 0", flag.bits())
+                    }
+                };
+            }
+
+            out.push(')');
+        }
+
+        out.push(')');
+        out
+    }
+
+    /// Binds the values of the condition returned by `sql_condition`
+    pub fn bind(&self, mut query: Query) -> Query {
+        for term in self.groups.iter().flatten() {
+            query = match &term.filter {
+                SearchFilter::Text(text) => query.bind(text),
+                SearchFilter::Regex(regex) => query.bind(regex.as_str()),
+                SearchFilter::UserLogin(value) | SearchFilter::UserId(value) => query.bind(value),
+                SearchFilter::Badge(badge) => {
+                    if badge.contains('/') {
+                        query.bind(badge)
+                    } else {
+                        query.bind(format!("{badge}/"))
+                    }
+                }
+                SearchFilter::Type(_) | SearchFilter::Flag(_) => query,
+            };
+        }
+        query
+    }
+
+    pub fn matches(&self, msg: &StructuredMessage) -> bool {
+        self.groups.iter().any(|group| {
+            group
+                .iter()
+                .all(|term| term.filter.matches(msg) != term.negated)
+        })
+    }
+}
+
+impl SearchFilter {
+    fn matches(&self, msg: &StructuredMessage) -> bool {
+        match self {
+            SearchFilter::Text(text) => msg.text().to_lowercase().contains(&text.to_lowercase()),
+            SearchFilter::Regex(regex) => regex.is_match(msg.text()),
+            SearchFilter::UserLogin(login) => msg.user_login == *login,
+            SearchFilter::UserId(id) => msg.user_id == *id,
+            SearchFilter::Type(message_type) => msg.message_type == *message_type,
+            SearchFilter::Badge(badge) => msg.badges.iter().any(|value| {
+                if badge.contains('/') {
+                    value == badge
+                } else {
+                    value.split('/').next() == Some(badge.as_str())
+                }
+            }),
+            SearchFilter::Flag(flag) => msg.message_flags.intersects(*flag),
+        }
+    }
+}
+
+fn parse_word(word: String) -> Result<SearchFilter> {
+    let Some((field, value)) = word.split_once(':').filter(|(_, value)| !value.is_empty()) else {
+        return Ok(SearchFilter::Text(word));
+    };
+
+    let filter = match field {
+        "user" => SearchFilter::UserLogin(value.to_lowercase()),
+        "userid" => SearchFilter::UserId(value.to_owned()),
+        "type" => {
+            let message_type = MessageType::from_str(&value.to_uppercase())
+                .map_err(|_| Error::InvalidParam(format!("Unknown message type `{value}`")))?;
+            SearchFilter::Type(message_type)
+        }
+        "badge" => SearchFilter::Badge(value.to_lowercase()),
+        "flag" => {
+            let flag = MessageFlags::from_name(&value.to_uppercase())
+                .ok_or_else(|| Error::InvalidParam(format!("Unknown message flag `{value}`")))?;
+            SearchFilter::Flag(flag)
+        }
+        _ => SearchFilter::Text(word),
+    };
+    Ok(filter)
+}
+
+fn read_until(chars: &mut Peekable<Chars>, end: char) -> String {
+    let mut out = String::new();
+
+    while let Some(c) = chars.next() {
+        if c == end {
+            break;
+        }
+
+        if c == '\\' && chars.peek() == Some(&end) {
+            out.push(end);
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SearchFilter, SearchQuery};
+    use crate::db::schema::{MessageFlags, MessageType, StructuredMessage, UnstructuredMessage};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_terms() {
+        let query = SearchQuery::parse(
+            r#"hello "quoted phrase" -exclude /fo+\/bar/ user:Forsen type:usernotice OR badge:moderator flag:first_msg"#,
+        )
+        .unwrap();
+
+        assert_eq!(2, query.groups.len());
+
+        let first = &query.groups[0];
+        assert!(matches!(&first[0].filter, SearchFilter::Text(text) if text == "hello"));
+        assert!(matches!(&first[1].filter, SearchFilter::Text(text) if text == "quoted phrase"));
+        assert!(first[2].negated);
+        assert!(matches!(&first[2].filter, SearchFilter::Text(text) if text == "exclude"));
+        assert!(
+            matches!(&first[3].filter, SearchFilter::Regex(regex) if regex.as_str() == "fo+/bar")
+        );
+        assert!(matches!(&first[4].filter, SearchFilter::UserLogin(login) if login == "forsen"));
+        assert!(matches!(
+            &first[5].filter,
+            SearchFilter::Type(MessageType::UserNotice)
+        ));
+
+        let second = &query.groups[1];
+        assert!(matches!(&second[0].filter, SearchFilter::Badge(badge) if badge == "moderator"));
+        assert!(matches!(
+            &second[1].filter,
+            SearchFilter::Flag(MessageFlags::FIRST_MSG)
+        ));
+    }
+
+    #[test]
+    fn sql_condition() {
+        let query = SearchQuery::parse("hello -type:clearchat OR flag:mod").unwrap();
+        assert_eq!(
+            "((positionCaseInsensitiveUTF8(text, ?) != 0 AND NOT message_type = 2) OR (bitAnd(message_flags, 4) != 0))",
+            query.sql_condition()
+        );
+    }
+
+    #[test]
+    fn invalid_queries() {
+        assert!(SearchQuery::parse("   ").is_err());
+        assert!(SearchQuery::parse("OR").is_err());
+        assert!(SearchQuery::parse("/(unclosed/").is_err());
+        assert!(SearchQuery::parse("type:something").is_err());
+        assert!(SearchQuery::parse("flag:something").is_err());
+    }
+
+    #[test]
+    fn match_message() {
+        let unstructured = UnstructuredMessage {
+            channel_id: "22484632",
+            user_id: "68136884",
+            timestamp: 1709251274940,
+            raw: "@returning-chatter=0;user-id=68136884;user-type=;badges=vip/1,subscriber/60;mod=0;display-name=Supibot;room-id=22484632;flags=;emotes=;first-msg=0;vip=1;tmi-sent-ts=1709251274940;id=272e342c-5864-4c59-b730-25908cdb7f57;subscriber=1;turbo=0;color=#1E90FF;badge-info=subscriber/65 :supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #forsen :+join the Raffle",
+        };
+        let message = StructuredMessage::from_unstructured(&unstructured).unwrap();
+
+        let matches = |input: &str| SearchQuery::parse(input).unwrap().matches(&message);
+
+        assert!(matches("raffle"));
+        assert!(matches("\"join the\" user:supibot"));
+        assert!(matches("/^\\+join/ badge:vip flag:subscriber"));
+        assert!(matches("badge:subscriber/60 type:privmsg"));
+        assert!(matches("nothing OR raffle"));
+        assert!(!matches("raffle -user:supibot"));
+        assert!(!matches("badge:moderator OR flag:first_msg"));
+        assert!(!matches("badge:subscriber/12"));
+    }
+}
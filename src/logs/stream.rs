@@ -6,9 +6,10 @@ pub use buffer_response::FlushBufferResponse;
 use cursor::CursorStream;
 use multi_query::MultiQueryStream;
 
+use super::pagination::PageCursor;
 use crate::{db::schema::StructuredMessage, error::Error, Result};
 use clickhouse::query::RowCursor;
-use futures::{Stream, StreamExt};
+use futures::{Stream, StreamExt, TryStreamExt};
 use std::{
     ops::DerefMut,
     pin::Pin,
@@ -16,7 +17,7 @@ use std::{
 };
 
 pub enum LogsStream {
-    Cursor(CursorStream),
+    Cursor(Box<CursorStream>),
     MultiQuery(MultiQueryStream),
     Provided(Option<Vec<StructuredMessage<'static>>>),
 }
@@ -26,9 +27,9 @@ impl LogsStream {
         cursor: RowCursor<StructuredMessage<'static>>,
         buffer_response: FlushBufferResponse,
     ) -> Result<Self> {
-        Ok(Self::Cursor(
+        Ok(Self::Cursor(Box::new(
             CursorStream::new(cursor, buffer_response).await?,
-        ))
+        )))
     }
 
     pub fn new_provided(messages: Vec<StructuredMessage<'static>>) -> Result<Self> {
@@ -52,6 +53,26 @@ impl LogsStream {
             buffer_response,
         )))
     }
+
+    /// Reads a page of up to `limit` messages from a stream that was queried with `LogsParams::with_lookahead`,
+    /// returning the cursor of the next page if there are more messages.
+    /// The page is buffered in memory, which is why `LogsParams::validate` bounds the limit
+    pub async fn into_page(self, limit: Option<u64>) -> Result<(Self, Option<PageCursor>)> {
+        let Some(limit) = limit else {
+            return Ok((self, None));
+        };
+
+        let mut messages: Vec<_> = self.try_concat().await?;
+
+        let next_cursor = if messages.len() as u64 > limit {
+            messages.truncate(limit as usize);
+            messages.last().map(PageCursor::from_message)
+        } else {
+            None
+        };
+
+        Ok((Self::new_provided(messages)?, next_cursor))
+    }
 }
 
 impl Stream for LogsStream {
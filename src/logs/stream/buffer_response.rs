@@ -2,7 +2,8 @@ use chrono::{DateTime, Utc};
 
 use crate::{
     db::{schema::StructuredMessage, writer::FlushBuffer},
-    web::schema::LogsParams,
+    logs::{deletions::MessageDeletions, search::SearchQuery},
+    web::schema::{DeletedFilter, LogsParams},
 };
 
 #[derive(Debug)]
@@ -28,7 +29,7 @@ impl FlushBufferResponse {
     ) -> Self {
         let timestamp_range = (from.timestamp_millis() as u64)..(to.timestamp_millis() as u64);
 
-        let mut messages = if let Some(user_id) = user_id {
+        let messages = if let Some(user_id) = user_id {
             buffer
                 .messages_by_channel_and_user(timestamp_range, channel_id, user_id)
                 .await
@@ -37,11 +38,37 @@ impl FlushBufferResponse {
                 .messages_by_channel(timestamp_range, channel_id)
                 .await
         };
+        let messages = filter_deleted(buffer, channel_id, messages, params).await;
 
+        Self::from_messages(messages, params)
+    }
+
+    pub async fn new_search(
+        buffer: &FlushBuffer,
+        channel_id: &str,
+        search: &SearchQuery,
+        params: LogsParams,
+        (from, to): (DateTime<Utc>, DateTime<Utc>),
+    ) -> Self {
+        let timestamp_range = (from.timestamp_millis() as u64)..(to.timestamp_millis() as u64);
+
+        let messages = buffer
+            .search_messages_by_channel(timestamp_range, channel_id, search)
+            .await;
+        let messages = filter_deleted(buffer, channel_id, messages, params).await;
+
+        Self::from_messages(messages, params)
+    }
+
+    fn from_messages(mut messages: Vec<StructuredMessage<'static>>, params: LogsParams) -> Self {
         if params.reverse {
             messages.reverse();
         }
 
+        if let Some(cursor) = params.cursor {
+            messages.retain(|msg| cursor.is_followed_by(msg, params.reverse));
+        }
+
         if let Some(offset) = params.offset {
             if offset as usize > messages.len() {
                 messages.clear();
@@ -87,3 +114,21 @@ impl FlushBufferResponse {
         self.params.reverse
     }
 }
+
+/// Applies the `deleted` filter, using the deletions from the buffer as they always come after the buffered messages
+async fn filter_deleted(
+    buffer: &FlushBuffer,
+    channel_id: &str,
+    mut messages: Vec<StructuredMessage<'static>>,
+    params: LogsParams,
+) -> Vec<StructuredMessage<'static>> {
+    let Some(filter) = params.deleted else {
+        return messages;
+    };
+
+    let mut deletions = MessageDeletions::default();
+    deletions.extend_from_messages(&buffer.messages_by_channel(0..u64::MAX, channel_id).await);
+
+    messages.retain(|msg| deletions.contains(msg) == (filter == DeletedFilter::Only));
+    messages
+}
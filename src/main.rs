@@ -1,9 +1,12 @@
 mod app;
+mod archive;
 mod args;
 mod bot;
 mod config;
 mod db;
 mod error;
+mod exporter;
+mod importer;
 mod logs;
 mod migrator;
 mod web;
@@ -13,21 +16,27 @@ pub type ShutdownRx = watch::Receiver<()>;
 
 use anyhow::{anyhow, Context};
 use app::App;
+use archive::{archive_partitions, ColdArchive};
 use args::{Args, Command};
+use bot::ConfigTokenStorage;
 use clap::Parser;
 use config::Config;
-use db::{setup_db, writer::create_writer};
+use dashmap::DashMap;
+use db::{dedupe::remove_duplicates, setup_db, writer::create_writer};
+use exporter::{export_channel, ArchiveCompression};
 use futures::{future::try_join_all, stream::FuturesUnordered, StreamExt};
+use importer::{import_file, ImportFormat};
 use migrator::Migrator;
 use mimalloc::MiMalloc;
 use std::{
     env,
+    path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
 use tokio::{
     signal::unix::{signal, SignalKind},
-    sync::{mpsc, watch},
+    sync::{broadcast, mpsc, watch},
     time::timeout,
 };
 use tracing::{debug, info};
@@ -36,11 +45,12 @@ use twitch_api::{
     twitch_oauth2::{AppAccessToken, Scope},
     HelixClient,
 };
-use twitch_irc::login::StaticLoginCredentials;
+use twitch_irc::login::{RefreshingLoginCredentials, StaticLoginCredentials};
 
-use crate::app::cache::UsersCache;
+use crate::app::{cache::UsersCache, export::ExportJobs};
 
 const SHUTDOWN_TIMEOUT_SECONDS: u64 = 8;
+const LIVE_MESSAGES_CAPACITY: usize = 1000;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -84,7 +94,51 @@ async fn main() -> anyhow::Result<()> {
             source_dir,
             channel_id,
             jobs,
-        }) => migrate(db, source_dir, channel_id, jobs).await,
+            resume,
+            dry_run,
+            verify,
+        }) => {
+            let migrator = Migrator::new(db, source_dir, channel_id, resume).await?;
+            if dry_run {
+                migrator.dry_run(jobs).await
+            } else if verify {
+                migrator.verify(jobs).await
+            } else {
+                migrator.run(jobs).await
+            }
+        }
+        Some(Command::Export {
+            target_dir,
+            channel_id,
+            compression,
+        }) => {
+            let excluded_user_ids = config.opted_out_user_ids();
+            export(db, target_dir, channel_id, &excluded_user_ids, compression).await
+        }
+        Some(Command::Import {
+            file,
+            format,
+            channel_id,
+        }) => import(db, file, format, channel_id, &config.opt_out).await,
+        Some(Command::Dedupe) => dedupe(db).await,
+        Some(Command::Archive {
+            months,
+            compression,
+        }) => {
+            let root_path = config
+                .cold_archive_path
+                .as_deref()
+                .context("`coldArchivePath` needs to be set to archive partitions")?;
+            let excluded_user_ids = config.opted_out_user_ids();
+            archive_partitions(
+                &db,
+                Path::new(root_path),
+                months,
+                &excluded_user_ids,
+                compression,
+            )
+            .await
+        }
     }
 }
 
@@ -93,34 +147,78 @@ async fn run(config: Config, db: clickhouse::Client) -> anyhow::Result<()> {
 
     let helix_client: HelixClient<reqwest::Client> = HelixClient::default();
     let token = generate_token(&config).await?;
+    let config = Arc::new(config);
 
-    let (writer_tx, flush_buffer, mut writer_handle) = create_writer(
-        db.clone(),
-        shutdown_rx.clone(),
-        config.clickhouse_flush_interval,
-    )
-    .await?;
+    let (writer_tx, flush_buffer, mut writer_handle) =
+        create_writer(db.clone(), shutdown_rx.clone(), config.clone()).await?;
+
+    let (live_messages, _) = broadcast::channel(LIVE_MESSAGES_CAPACITY);
+    let cold_archive = config
+        .cold_archive_path
+        .as_ref()
+        .map(|root_path| Arc::new(ColdArchive::new(PathBuf::from(root_path))));
 
     let app = App {
         helix_client,
         token: Arc::new(token),
         users: UsersCache::default(),
-        config: Arc::new(config),
+        config,
         db: Arc::new(db),
         optout_codes: Arc::default(),
         flush_buffer,
+        live_messages,
+        export_jobs: ExportJobs::default(),
+        cold_archive,
     };
 
     let (bot_tx, bot_rx) = mpsc::channel(1);
 
-    let login_credentials = StaticLoginCredentials::anonymous();
-    let mut bot_handle = tokio::spawn(bot::run(
-        login_credentials,
-        app.clone(),
-        writer_tx,
+    let bot_login = app.config.bot_login.clone();
+    let bot_token = app.config.bot_token.read().unwrap().clone();
+    let mut bot_handle = match (bot_login, bot_token) {
+        (Some(login), Some(token)) if token.refresh_token.is_some() => {
+            info!("Logging in to chat as {login} with a refreshing token");
+            let login_credentials = RefreshingLoginCredentials::init_with_username(
+                Some(login),
+                app.config.client_id.clone(),
+                app.config.client_secret.clone(),
+                ConfigTokenStorage::new(app.config.clone()),
+            );
+            tokio::spawn(bot::run(
+                login_credentials,
+                app.clone(),
+                writer_tx,
+                shutdown_rx.clone(),
+                bot_rx,
+            ))
+        }
+        (Some(login), Some(token)) => {
+            info!("Logging in to chat as {login}");
+            let access_token = token.access_token.trim_start_matches("oauth:").to_owned();
+            let login_credentials = StaticLoginCredentials::new(login, Some(access_token));
+            tokio::spawn(bot::run(
+                login_credentials,
+                app.clone(),
+                writer_tx,
+                shutdown_rx.clone(),
+                bot_rx,
+            ))
+        }
+        (None, None) => tokio::spawn(bot::run(
+            StaticLoginCredentials::anonymous(),
+            app.clone(),
+            writer_tx,
+            shutdown_rx.clone(),
+            bot_rx,
+        )),
+        _ => return Err(anyhow!("`botLogin` and `botToken` need to be set together")),
+    };
+    tokio::spawn(app::purge::run(
+        app.db.clone(),
+        app.cold_archive.clone(),
         shutdown_rx.clone(),
-        bot_rx,
     ));
+    tokio::spawn(app::retention::run(app.clone(), shutdown_rx.clone()));
     let mut web_handle = tokio::spawn(web::run(app, shutdown_rx.clone(), bot_tx));
 
     tokio::select! {
@@ -154,14 +252,58 @@ async fn run(config: Config, db: clickhouse::Client) -> anyhow::Result<()> {
     }
 }
 
-async fn migrate(
+async fn export(
     db: clickhouse::Client,
-    source_logs_path: String,
+    target_dir: String,
     channel_ids: Vec<String>,
-    jobs: usize,
+    excluded_user_ids: &[String],
+    compression: ArchiveCompression,
 ) -> anyhow::Result<()> {
-    let migrator = Migrator::new(db, source_logs_path, channel_ids).await?;
-    migrator.run(jobs).await
+    let root_path = PathBuf::from(target_dir);
+
+    for channel_id in channel_ids {
+        export_channel(
+            &db,
+            &channel_id,
+            excluded_user_ids,
+            &root_path,
+            compression,
+            |day, count| {
+                debug!("Exported {count} messages from {}", day.date_naive());
+            },
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn import(
+    db: clickhouse::Client,
+    files: Vec<String>,
+    format: ImportFormat,
+    default_channel_id: Option<String>,
+    opt_out: &DashMap<String, bool>,
+) -> anyhow::Result<()> {
+    for file in files {
+        import_file(
+            &db,
+            Path::new(&file),
+            format,
+            default_channel_id.as_deref(),
+            opt_out,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn dedupe(db: clickhouse::Client) -> anyhow::Result<()> {
+    let removed = remove_duplicates(&db).await?;
+    info!("Removed {removed} duplicate messages");
+
+    Ok(())
 }
 
 async fn generate_token(config: &Config) -> anyhow::Result<AppAccessToken> {
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use clickhouse::Row;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const CHECKPOINTS_TABLE: &str = "__rustlog_migration_checkpoints";
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// A justlog day file whose messages have all been inserted
+#[derive(Row, Serialize, Deserialize, Debug)]
+pub struct MigrationCheckpoint {
+    pub channel_id: String,
+    /// Days since the unix epoch
+    pub date: u16,
+    pub file_size: u64,
+    pub line_count: u64,
+    pub completed_at: u32,
+}
+
+impl MigrationCheckpoint {
+    pub fn new(channel_id: &str, date: DateTime<Utc>, file_size: u64, line_count: u64) -> Self {
+        Self {
+            channel_id: channel_id.to_owned(),
+            date: day_number(date),
+            file_size,
+            line_count,
+            completed_at: Utc::now().timestamp() as u32,
+        }
+    }
+}
+
+pub fn day_number(date: DateTime<Utc>) -> u16 {
+    (date.timestamp() / SECONDS_PER_DAY) as u16
+}
+
+/// File sizes of the completed days, by channel id and day number
+pub async fn read_completed_days(
+    db: &clickhouse::Client,
+    channel_ids: &[String],
+) -> clickhouse::error::Result<HashMap<(String, u16), u64>> {
+    let checkpoints = db
+        .query(&format!(
+            "SELECT ?fields FROM {CHECKPOINTS_TABLE} FINAL WHERE has(?, channel_id)"
+        ))
+        .bind(channel_ids)
+        .fetch_all::<MigrationCheckpoint>()
+        .await?;
+
+    Ok(checkpoints
+        .into_iter()
+        .map(|checkpoint| {
+            (
+                (checkpoint.channel_id, checkpoint.date),
+                checkpoint.file_size,
+            )
+        })
+        .collect())
+}
+
+pub async fn write_checkpoints(
+    db: &clickhouse::Client,
+    checkpoints: &[MigrationCheckpoint],
+) -> clickhouse::error::Result<()> {
+    if checkpoints.is_empty() {
+        return Ok(());
+    }
+
+    let mut insert = db.insert(CHECKPOINTS_TABLE)?;
+    for checkpoint in checkpoints {
+        insert.write(checkpoint).await?;
+    }
+    insert.end().await
+}
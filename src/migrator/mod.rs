@@ -1,8 +1,16 @@
-mod reader;
+mod checkpoint;
+pub mod reader;
+mod verify;
 
-use self::reader::{LogsReader, COMPRESSED_CHANNEL_FILE, UNCOMPRESSED_CHANNEL_FILE};
+use self::checkpoint::{day_number, read_completed_days, write_checkpoints, MigrationCheckpoint};
+use self::reader::{
+    LogsReader, COMPRESSED_CHANNEL_FILE, UNCOMPRESSED_CHANNEL_FILE, ZSTD_CHANNEL_FILE,
+};
 use crate::{
-    db::schema::{StructuredMessage, UnstructuredMessage, MESSAGES_STRUCTURED_TABLE},
+    db::{
+        dedupe::read_stored_keys,
+        schema::{MessageType, StructuredMessage, UnstructuredMessage, MESSAGES_STRUCTURED_TABLE},
+    },
     logs::extract::{extract_raw_timestamp, extract_user_id},
     migrator::reader::ChannelLogDateMap,
 };
@@ -12,6 +20,7 @@ use clickhouse::inserter::Inserter;
 use flate2::bufread::GzDecoder;
 use indexmap::IndexMap;
 use std::{
+    collections::{HashMap, HashSet},
     convert::TryInto,
     fs::File,
     io::{BufRead, BufReader},
@@ -25,14 +34,24 @@ use std::{
 use tmi::Command;
 use tokio::sync::Semaphore;
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 const INSERT_BATCH_SIZE: u64 = 10_000_000;
+const MILLIS_PER_DAY: u64 = 86_400_000;
 
 #[derive(Clone)]
 pub struct Migrator {
     db: clickhouse::Client,
     source_logs_path: String,
     channel_ids: Arc<Vec<String>>,
+    resume: bool,
+}
+
+/// What was read from a single day file
+struct DayStats {
+    read_bytes: usize,
+    line_count: u64,
+    file_size: u64,
 }
 
 impl Migrator {
@@ -40,11 +59,13 @@ impl Migrator {
         db: clickhouse::Client,
         source_logs_path: String,
         channel_ids: Vec<String>,
+        resume: bool,
     ) -> anyhow::Result<Migrator> {
         Ok(Self {
             db,
             source_logs_path,
             channel_ids: Arc::new(channel_ids),
+            resume,
         })
     }
 
@@ -52,16 +73,11 @@ impl Migrator {
         let source_logs = LogsReader::new(&self.source_logs_path)?;
 
         let started_at = Instant::now();
-        let channels = source_logs.get_stored_channels().await?;
+        let filtered_channels = self.source_channels(&source_logs).await?;
 
         let semaphore = Arc::new(Semaphore::new(parallel_count));
         let mut handles = Vec::with_capacity(parallel_count);
 
-        let filtered_channels: Vec<_> = channels
-            .into_iter()
-            .filter(|channel| self.channel_ids.is_empty() || self.channel_ids.contains(channel))
-            .collect();
-
         info!("Migrating channels {filtered_channels:?}");
 
         let mut channel_logs: IndexMap<String, ChannelLogDateMap> = IndexMap::new();
@@ -77,18 +93,28 @@ impl Migrator {
             channel_logs.insert(channel_id, available_logs);
         }
 
+        let completed_days = if self.resume {
+            let channel_ids: Vec<String> = channel_logs.keys().cloned().collect();
+            let completed_days = read_completed_days(&self.db, &channel_ids)
+                .await
+                .context("Could not read migration checkpoints")?;
+            info!("Skipping {} already migrated days", completed_days.len());
+            completed_days
+        } else {
+            HashMap::new()
+        };
+        let completed_days = Arc::new(completed_days);
+
         let channel_count = channel_logs.len();
         let total_mb = total_bytes / 1024 / 1024;
 
         info!("Migrating {channel_count} channels with {total_mb} MiB of logs");
         info!("NOTE: the estimation numbers will be wrong if you use gzip compressed logs");
 
-        let mut i = 1;
-
         let total_read_bytes = Arc::new(AtomicU64::new(0));
         let migrated_percentage = Arc::new(AtomicU64::new(0));
 
-        for (channel_id, available_logs) in channel_logs {
+        for (i, (channel_id, available_logs)) in (1..).zip(channel_logs) {
             info!("Reading channel {channel_id} ({i}/{channel_count})");
 
             for (year, months) in available_logs {
@@ -100,6 +126,7 @@ impl Migrator {
                     let root_path = source_logs.root_path.clone();
                     let total_read_bytes = total_read_bytes.clone();
                     let migrated_percentage = migrated_percentage.clone();
+                    let completed_days = completed_days.clone();
 
                     let handle = tokio::spawn(async move {
                         let mut inserter = migrator
@@ -114,16 +141,61 @@ impl Migrator {
 
                         info!("Migrating channel {channel_id} date {year}-{month}");
 
+                        // Days whose messages were written to the inserter but not inserted yet
+                        let mut pending_checkpoints = Vec::new();
+
                         for day in days {
                             let date = Utc
                                 .with_ymd_and_hms(year.try_into().unwrap(), month, day, 0, 0, 0)
                                 .unwrap();
-                            let day_bytes = migrator
-                                .migrate_day(&root_path, &channel_id, date, &mut inserter)
-                                .await
-                                .with_context(|| {
-                                    format!("Could not migrate channel {channel_id} date {date}")
-                                })?;
+
+                            // The file could have been appended to after its checkpoint was written
+                            let mut skipped_size = None;
+                            if let Some(&completed_size) =
+                                completed_days.get(&(channel_id.clone(), day_number(date)))
+                            {
+                                let (_, current_size) =
+                                    open_day_file(&root_path, &channel_id, date)?;
+                                if current_size == completed_size {
+                                    skipped_size = Some(current_size);
+                                } else {
+                                    warn!("Log file of channel {channel_id} date {date} changed from {completed_size} to {current_size} bytes since it was migrated, migrating it again");
+                                }
+                            }
+
+                            let day_bytes = if let Some(file_size) = skipped_size {
+                                debug!("Skipping migrated channel {channel_id} date {date}");
+                                file_size as usize
+                            } else {
+                                let day_stats = migrator
+                                    .migrate_day(&root_path, &channel_id, date, &mut inserter)
+                                    .await
+                                    .with_context(|| {
+                                        format!(
+                                            "Could not migrate channel {channel_id} date {date}"
+                                        )
+                                    })?;
+                                pending_checkpoints.push(MigrationCheckpoint::new(
+                                    &channel_id,
+                                    date,
+                                    day_stats.file_size,
+                                    day_stats.line_count,
+                                ));
+
+                                let stats = inserter.commit().await?;
+                                if stats.rows > 0 {
+                                    info!(
+                                        "DB: {} entries ({} transactions) have been inserted",
+                                        stats.rows, stats.transactions,
+                                    );
+                                    write_checkpoints(&migrator.db, &pending_checkpoints)
+                                        .await
+                                        .context("Could not save migration checkpoints")?;
+                                    pending_checkpoints.clear();
+                                }
+
+                                day_stats.read_bytes
+                            };
 
                             total_read_bytes.fetch_add(day_bytes as u64, Ordering::SeqCst);
                             let processed_bytes = total_read_bytes.load(Ordering::SeqCst);
@@ -149,6 +221,9 @@ impl Migrator {
                                 stats.rows, stats.transactions,
                             );
                         }
+                        write_checkpoints(&migrator.db, &pending_checkpoints)
+                            .await
+                            .context("Could not save migration checkpoints")?;
 
                         drop(permit);
                         Result::<_, anyhow::Error>::Ok(())
@@ -156,7 +231,6 @@ impl Migrator {
                     handles.push(handle);
                 }
             }
-            i += 1;
         }
 
         for handle in handles {
@@ -175,104 +249,176 @@ impl Migrator {
         Ok(())
     }
 
-    // Returns the number of read bytes
+    /// Channels in the source folder, filtered by the requested channel ids
+    async fn source_channels(&self, source_logs: &LogsReader) -> anyhow::Result<Vec<String>> {
+        let channels = source_logs.get_stored_channels().await?;
+
+        Ok(channels
+            .into_iter()
+            .filter(|channel| self.channel_ids.is_empty() || self.channel_ids.contains(channel))
+            .collect())
+    }
+
+    /// Writes the messages of the day to the inserter, without committing them
     async fn migrate_day<'a>(
         &self,
         root_path: &Path,
         channel_id: &'a str,
         date: DateTime<Utc>,
         inserter: &mut Inserter<StructuredMessage<'a>>,
-    ) -> anyhow::Result<usize> {
-        let day_path = get_day_path(root_path, channel_id, date);
-
-        let compressed_file_path = day_path.join(COMPRESSED_CHANNEL_FILE);
-        let uncompressed_file_path = day_path.join(UNCOMPRESSED_CHANNEL_FILE);
-
-        if compressed_file_path.exists() {
-            debug!("Reading compressed log {compressed_file_path:?}");
-            let file_reader = BufReader::new(File::open(&compressed_file_path)?);
-            let gz = BufReader::new(GzDecoder::new(file_reader));
-
-            self.migrate_reader(gz, date, channel_id, inserter).await
-        } else if uncompressed_file_path.exists() {
-            debug!("Reading uncompressed log {uncompressed_file_path:?}");
-            let file_reader = BufReader::new(File::open(&uncompressed_file_path)?);
-
-            self.migrate_reader(file_reader, date, channel_id, inserter)
-                .await
-        } else {
-            Err(anyhow!("File does not exist"))
-        }
+    ) -> anyhow::Result<DayStats> {
+        let (reader, file_size) = open_day_file(root_path, channel_id, date)?;
+        let day_start = date.timestamp_millis() as u64;
+        let mut seen_keys =
+            read_stored_keys(&self.db, channel_id, day_start..day_start + MILLIS_PER_DAY).await?;
+
+        self.migrate_reader(
+            reader,
+            file_size,
+            date,
+            channel_id,
+            inserter,
+            &mut seen_keys,
+        )
+        .await
     }
 
     async fn migrate_reader<'a, R: BufRead>(
         &self,
         reader: R,
+        file_size: u64,
         datetime: DateTime<Utc>,
         channel_id: &'a str,
         inserter: &mut Inserter<StructuredMessage<'a>>,
-    ) -> anyhow::Result<usize> {
+        seen_keys: &mut HashSet<Uuid>,
+    ) -> anyhow::Result<DayStats> {
         let mut read_bytes = 0;
+        let mut line_count = 0;
 
         for (i, line) in reader.lines().enumerate() {
             let line = line.with_context(|| format!("Could not read line {i} from input"))?;
             read_bytes += line.len() + 1; // Add 1 byte for newline symbol
-            write_line(channel_id, line, inserter, datetime)
+            line_count += 1;
+            write_line(channel_id, line, inserter, seen_keys, datetime)
                 .await
                 .with_context(|| format!("Could not write line {i} to inserter"))?;
         }
 
-        let stats = inserter.commit().await?;
-        if stats.rows > 0 {
-            info!(
-                "DB: {} entries ({} transactions) have been inserted",
-                stats.rows, stats.transactions,
-            );
-        }
+        Ok(DayStats {
+            read_bytes,
+            line_count,
+            file_size,
+        })
+    }
+}
+
+/// Opens the log file of the day, returning it with its size on disk
+pub fn open_day_file(
+    root_path: &Path,
+    channel_id: &str,
+    date: DateTime<Utc>,
+) -> anyhow::Result<(Box<dyn BufRead + Send>, u64)> {
+    let day_path = get_day_path(root_path, channel_id, date);
+
+    let compressed_file_path = day_path.join(COMPRESSED_CHANNEL_FILE);
+    let uncompressed_file_path = day_path.join(UNCOMPRESSED_CHANNEL_FILE);
+    let zstd_file_path = day_path.join(ZSTD_CHANNEL_FILE);
+
+    if compressed_file_path.exists() {
+        debug!("Reading compressed log {compressed_file_path:?}");
+        let file = File::open(&compressed_file_path)?;
+        let file_size = file.metadata()?.len();
+        let gz = BufReader::new(GzDecoder::new(BufReader::new(file)));
+
+        Ok((Box::new(gz), file_size))
+    } else if zstd_file_path.exists() {
+        debug!("Reading zstd compressed log {zstd_file_path:?}");
+        let file = File::open(&zstd_file_path)?;
+        let file_size = file.metadata()?.len();
+        let zstd = BufReader::new(zstd::Decoder::with_buffer(BufReader::new(file))?);
+
+        Ok((Box::new(zstd), file_size))
+    } else if uncompressed_file_path.exists() {
+        debug!("Reading uncompressed log {uncompressed_file_path:?}");
+        let file = File::open(&uncompressed_file_path)?;
+        let file_size = file.metadata()?.len();
+
+        Ok((Box::new(BufReader::new(file)), file_size))
+    } else {
+        Err(anyhow!("File does not exist"))
+    }
+}
 
-        Ok(read_bytes)
+pub enum LineOutcome<T> {
+    /// Result of the callback given the converted message
+    Parsed(T),
+    UnknownType,
+    Unparsable,
+}
+
+/// Converts a line of a justlog file, using the date of the file when the message has no timestamp.
+///
+/// The converted message borrows from locals, so it is handed to `on_message` along with whether the user id is missing.
+pub fn parse_line<T>(
+    channel_id: &str,
+    raw: &str,
+    datetime: DateTime<Utc>,
+    on_message: impl FnOnce(StructuredMessage<'_>, bool) -> T,
+) -> LineOutcome<T> {
+    let Some(irc_message) = tmi::IrcMessageRef::parse(raw) else {
+        return LineOutcome::Unparsable;
+    };
+    if MessageType::from_tmi_command(irc_message.command()).is_none() {
+        return LineOutcome::UnknownType;
+    }
+
+    let timestamp =
+        extract_raw_timestamp(&irc_message).unwrap_or_else(|| datetime.timestamp_millis() as u64);
+    let user_id = extract_user_id(&irc_message);
+    let missing_user_id = user_id.is_none() && irc_message.command() == Command::Privmsg;
+
+    let unstructured = UnstructuredMessage {
+        channel_id,
+        user_id: user_id.unwrap_or_default(),
+        timestamp,
+        raw: irc_message.raw(),
+    };
+    match StructuredMessage::from_unstructured(&unstructured) {
+        Ok(message) => LineOutcome::Parsed(on_message(message, missing_user_id)),
+        Err(err) => {
+            debug!("Could not convert message {unstructured:?}: {err}");
+            LineOutcome::Unparsable
+        }
     }
 }
 
-async fn write_line<'a>(
-    channel_id: &'a str,
+async fn write_line(
+    channel_id: &str,
     raw: String,
     inserter: &mut Inserter<StructuredMessage<'_>>,
+    seen_keys: &mut HashSet<Uuid>,
     datetime: DateTime<Utc>,
 ) -> anyhow::Result<()> {
-    match tmi::IrcMessageRef::parse(&raw) {
-        Some(irc_message) => {
-            let timestamp = extract_raw_timestamp(&irc_message)
-                .unwrap_or_else(|| datetime.timestamp_millis() as u64);
-            let user_id = extract_user_id(&irc_message).unwrap_or_else(|| {
-                if irc_message.command() == Command::Privmsg {
-                    warn!(
-                        "Could not extract user id from PRIVMSG, partially malformed message: `{}`",
-                        irc_message.raw()
-                    );
-                }
-                ""
-            });
-
-            let unstructured = UnstructuredMessage {
-                channel_id,
-                user_id,
-                timestamp,
-                raw: irc_message.raw(),
-            };
-            match StructuredMessage::from_unstructured(&unstructured) {
-                Ok(msg) => {
-                    // This is safe because despite the function signature,
-                    // `inserter.write` only uses the value for serialization at the time of the method call, and not later
-                    let msg: StructuredMessage<'static> = unsafe { std::mem::transmute(msg) };
-                    inserter.write(&msg)?;
-                }
-                Err(err) => {
-                    error!("Could not convert message {unstructured:?}: {err}");
-                }
-            }
+    let outcome = parse_line(channel_id, &raw, datetime, |message, missing_user_id| {
+        if !seen_keys.insert(message.dedupe_key()) {
+            debug!("Skipping duplicate message `{raw}`");
+            return Ok(());
+        }
+        if missing_user_id {
+            warn!("Could not extract user id from PRIVMSG, partially malformed message: `{raw}`");
+        }
+        // This is safe because despite the function signature,
+        // `inserter.write` only uses the value for serialization at the time of the method call, and not later
+        let msg: StructuredMessage<'static> = unsafe { std::mem::transmute(message) };
+        inserter.write(&msg)
+    });
+
+    match outcome {
+        LineOutcome::Parsed(result) => result?,
+        LineOutcome::UnknownType => {
+            error!("Could not convert message `{raw}`: unknown message type");
         }
-        None => {
+        LineOutcome::Unparsable => {
             warn!("Could not parse message `{raw}`");
         }
     }
@@ -280,7 +426,7 @@ async fn write_line<'a>(
     Ok(())
 }
 
-fn get_day_path(root_path: &Path, channel_id: &str, date: DateTime<Utc>) -> PathBuf {
+pub fn get_day_path(root_path: &Path, channel_id: &str, date: DateTime<Utc>) -> PathBuf {
     root_path
         .join(channel_id)
         .join(date.year().to_string())
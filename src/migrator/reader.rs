@@ -9,6 +9,7 @@ use tracing::info;
 
 pub const COMPRESSED_CHANNEL_FILE: &str = "channel.txt.gz";
 pub const UNCOMPRESSED_CHANNEL_FILE: &str = "channel.txt";
+pub const ZSTD_CHANNEL_FILE: &str = "channel.txt.zst";
 
 pub type ChannelLogDateMap = BTreeMap<u32, BTreeMap<u32, Vec<u32>>>;
 
@@ -78,9 +79,11 @@ impl LogsReader {
                         let compressed_channel_file_path = day_path.join(COMPRESSED_CHANNEL_FILE);
                         let uncompressed_channel_file_path =
                             day_path.join(UNCOMPRESSED_CHANNEL_FILE);
+                        let zstd_channel_file_path = day_path.join(ZSTD_CHANNEL_FILE);
 
                         if let Ok(metadata) = fs::metadata(uncompressed_channel_file_path)
                             .or_else(|_| fs::metadata(compressed_channel_file_path))
+                            .or_else(|_| fs::metadata(zstd_channel_file_path))
                         {
                             if metadata.is_file() {
                                 total_size += metadata.len();
@@ -0,0 +1,231 @@
+use super::reader::ChannelLogDateMap;
+use super::{open_day_file, parse_line, reader::LogsReader, LineOutcome, Migrator};
+use crate::db::dedupe::read_stored_keys;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, TimeZone, Utc};
+use std::{
+    collections::HashSet,
+    io::BufRead,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{sync::Semaphore, task::spawn_blocking};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// How the lines of the source files would be migrated
+#[derive(Default, Debug, Clone, Copy)]
+pub struct ParseStats {
+    pub parsed: u64,
+    pub unparsable: u64,
+    /// `PRIVMSG`s without a user id, which are still migrated
+    pub missing_user_id: u64,
+    pub unknown_type: u64,
+}
+
+impl ParseStats {
+    fn add(&mut self, other: &ParseStats) {
+        self.parsed += other.parsed;
+        self.unparsable += other.unparsable;
+        self.missing_user_id += other.missing_user_id;
+        self.unknown_type += other.unknown_type;
+    }
+}
+
+/// What was parsed from a day file
+struct DayScan {
+    stats: ParseStats,
+    /// Dedupe keys of the parsed messages, so messages repeated in the source are counted once
+    keys: HashSet<Uuid>,
+    /// Earliest and latest message timestamp, in milliseconds
+    timestamps: Option<(u64, u64)>,
+}
+
+impl Migrator {
+    /// Parses all source files without inserting anything, and reports the results per channel
+    pub async fn dry_run(self, parallel_count: usize) -> anyhow::Result<()> {
+        let source_logs = LogsReader::new(&self.source_logs_path)?;
+        let channels = self.source_channels(&source_logs).await?;
+        let semaphore = Arc::new(Semaphore::new(parallel_count));
+
+        let mut handles = Vec::with_capacity(channels.len());
+        for channel_id in channels {
+            let (available_logs, _) = source_logs.get_available_channel_logs(&channel_id)?;
+            let root_path = source_logs.root_path.clone();
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+
+            handles.push(spawn_blocking(move || {
+                info!("Reading channel {channel_id}");
+                let stats = scan_channel(&root_path, &channel_id, available_logs);
+                drop(permit);
+                stats.map(|stats| (channel_id, stats))
+            }));
+        }
+
+        let mut total = ParseStats::default();
+        for handle in handles {
+            let (channel_id, stats) = handle.await??;
+            log_stats(&channel_id, &stats);
+            total.add(&stats);
+        }
+        log_stats("total", &total);
+
+        Ok(())
+    }
+
+    /// Checks that every message of the source files is stored in the database.
+    /// Messages which only exist in the database, like the ones logged live, are not taken into account
+    pub async fn verify(self, parallel_count: usize) -> anyhow::Result<()> {
+        let source_logs = LogsReader::new(&self.source_logs_path)?;
+        let channels = self.source_channels(&source_logs).await?;
+        let semaphore = Arc::new(Semaphore::new(parallel_count));
+
+        let mut handles = Vec::with_capacity(channels.len());
+        for channel_id in channels {
+            let (available_logs, _) = source_logs.get_available_channel_logs(&channel_id)?;
+            let root_path = source_logs.root_path.clone();
+            let db = self.db.clone();
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+
+            handles.push(tokio::spawn(async move {
+                info!("Verifying channel {channel_id}");
+                let result = verify_channel(&db, root_path, &channel_id, available_logs)
+                    .await
+                    .with_context(|| format!("Could not verify channel {channel_id}"));
+                drop(permit);
+                result
+            }));
+        }
+
+        let mut mismatched_days = 0;
+        let mut checked_days = 0;
+        for handle in handles {
+            let (checked, mismatched) = handle.await??;
+            checked_days += checked;
+            mismatched_days += mismatched;
+        }
+
+        info!("Verified {checked_days} days, {mismatched_days} did not match");
+
+        if mismatched_days > 0 {
+            Err(anyhow!(
+                "{mismatched_days} days do not match the source files"
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returns how many days were checked and how many of them have messages missing from the database
+async fn verify_channel(
+    db: &clickhouse::Client,
+    root_path: Arc<PathBuf>,
+    channel_id: &str,
+    available_logs: ChannelLogDateMap,
+) -> anyhow::Result<(u64, u64)> {
+    let mut checked_days = 0;
+    let mut mismatched_days = 0;
+
+    for date in log_dates(available_logs) {
+        let scan = {
+            let root_path = root_path.clone();
+            let channel_id = channel_id.to_owned();
+            spawn_blocking(move || scan_day(&root_path, &channel_id, date))
+                .await?
+                .with_context(|| format!("Could not read date {date}"))?
+        };
+        checked_days += 1;
+
+        let Some((first_timestamp, last_timestamp)) = scan.timestamps else {
+            continue;
+        };
+        let stored_keys =
+            read_stored_keys(db, channel_id, first_timestamp..last_timestamp + 1).await?;
+
+        let missing_count = scan
+            .keys
+            .iter()
+            .filter(|key| !stored_keys.contains(key))
+            .count();
+        if missing_count > 0 {
+            mismatched_days += 1;
+            warn!(
+                "Channel {channel_id} date {}: {missing_count} out of {} messages in the source files are not in the database",
+                date.date_naive(),
+                scan.keys.len()
+            );
+        }
+    }
+
+    Ok((checked_days, mismatched_days))
+}
+
+fn scan_channel(
+    root_path: &Path,
+    channel_id: &str,
+    available_logs: ChannelLogDateMap,
+) -> anyhow::Result<ParseStats> {
+    let mut stats = ParseStats::default();
+
+    for date in log_dates(available_logs) {
+        let scan = scan_day(root_path, channel_id, date)
+            .with_context(|| format!("Could not read channel {channel_id} date {date}"))?;
+        stats.add(&scan.stats);
+    }
+
+    Ok(stats)
+}
+
+fn log_dates(available_logs: ChannelLogDateMap) -> impl Iterator<Item = DateTime<Utc>> {
+    available_logs.into_iter().flat_map(|(year, months)| {
+        months.into_iter().flat_map(move |(month, days)| {
+            days.into_iter().map(move |day| {
+                Utc.with_ymd_and_hms(year.try_into().unwrap(), month, day, 0, 0, 0)
+                    .unwrap()
+            })
+        })
+    })
+}
+
+fn scan_day(root_path: &Path, channel_id: &str, date: DateTime<Utc>) -> anyhow::Result<DayScan> {
+    let (reader, _) = open_day_file(root_path, channel_id, date)?;
+    let mut scan = DayScan {
+        stats: ParseStats::default(),
+        keys: HashSet::new(),
+        timestamps: None,
+    };
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.with_context(|| format!("Could not read line {i} from input"))?;
+
+        let outcome = parse_line(channel_id, &line, date, |message, missing_user_id| {
+            (message.dedupe_key(), message.timestamp, missing_user_id)
+        });
+
+        match outcome {
+            LineOutcome::Parsed((key, timestamp, missing_user_id)) => {
+                scan.stats.parsed += 1;
+                if missing_user_id {
+                    scan.stats.missing_user_id += 1;
+                }
+                scan.keys.insert(key);
+                scan.timestamps = Some(match scan.timestamps {
+                    Some((first, last)) => (first.min(timestamp), last.max(timestamp)),
+                    None => (timestamp, timestamp),
+                });
+            }
+            LineOutcome::UnknownType => scan.stats.unknown_type += 1,
+            LineOutcome::Unparsable => scan.stats.unparsable += 1,
+        }
+    }
+
+    Ok(scan)
+}
+
+fn log_stats(name: &str, stats: &ParseStats) {
+    info!(
+        "{name}: {} parsed lines, {} unparsable lines, {} lines with a missing user id, {} lines with an unknown message type",
+        stats.parsed, stats.unparsable, stats.missing_user_id, stats.unknown_type
+    );
+}
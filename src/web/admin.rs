@@ -1,4 +1,15 @@
-use crate::{app::App, bot::BotMessage, error::Error};
+use crate::{
+    app::{
+        export::ExportJob,
+        purge::{self, PurgeJob},
+        App,
+    },
+    bot::BotMessage,
+    db,
+    error::Error,
+    exporter::ArchiveCompression,
+    logs::schema::LogRangeParams,
+};
 use aide::{
     openapi::{
         HeaderStyle, Parameter, ParameterData, ParameterSchemaOrContent, ReferenceOr, SchemaObject,
@@ -6,14 +17,19 @@ use aide::{
     transform::TransformOperation,
 };
 use axum::{
-    extract::{Request, State},
+    body::Body,
+    extract::{Query, Request, State},
     middleware::Next,
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use reqwest::StatusCode;
+use reqwest::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    StatusCode,
+};
 use schemars::JsonSchema;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use tokio::sync::mpsc::Sender;
 
 pub async fn admin_auth(
@@ -92,3 +108,140 @@ pub async fn remove_channels(
 
     Ok(())
 }
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRequest {
+    pub channel_id: String,
+    #[serde(default)]
+    pub compression: ArchiveCompression,
+}
+
+pub async fn start_export(
+    app: State<App>,
+    Json(ExportRequest {
+        channel_id,
+        compression,
+    }): Json<ExportRequest>,
+) -> Result<StatusCode, Error> {
+    let Some(export_path) = &app.config.archive_export_path else {
+        return Err(Error::InvalidParam(
+            "Archive exports are not configured".to_owned(),
+        ));
+    };
+    if !channel_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error::InvalidParam("Invalid channel id".to_owned()));
+    }
+    app.check_opted_out(&channel_id, None)?;
+
+    if app.export_jobs.start(
+        app.db.clone(),
+        PathBuf::from(export_path),
+        channel_id,
+        app.config.opted_out_user_ids(),
+        compression,
+    ) {
+        Ok(StatusCode::ACCEPTED)
+    } else {
+        Ok(StatusCode::CONFLICT)
+    }
+}
+
+pub async fn list_exports(app: State<App>) -> Json<Vec<ExportJob>> {
+    Json(app.export_jobs.list())
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ParquetExportParams {
+    pub channel_id: String,
+}
+
+/// Streams the channel's messages with the same columns as the `message_structured` table
+pub async fn export_parquet(
+    app: State<App>,
+    Query(ParquetExportParams { channel_id }): Query<ParquetExportParams>,
+    Query(range_params): Query<LogRangeParams>,
+) -> Result<Response, Error> {
+    if !channel_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(Error::InvalidParam("Invalid channel id".to_owned()));
+    }
+    app.check_opted_out(&channel_id, None)?;
+
+    let opted_out_users = app.config.opted_out_user_ids();
+    let cursor = db::read_channel_parquet(&app.db, &channel_id, opted_out_users, range_params)?;
+
+    let headers = [
+        (CONTENT_TYPE, "application/vnd.apache.parquet".to_owned()),
+        (
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{channel_id}.parquet\""),
+        ),
+    ];
+    Ok((headers, Body::from_stream(cursor)).into_response())
+}
+
+pub async fn list_purges(app: State<App>) -> Result<Json<Vec<PurgeJob>>, Error> {
+    Ok(Json(purge::list(&app.db).await?))
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicy {
+    pub channel_id: String,
+    /// How many days of logs are kept
+    pub days: u32,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RetentionRequest {
+    /// List of channel ids
+    pub channels: Vec<String>,
+    /// How many days of logs to keep
+    pub days: u32,
+}
+
+pub async fn list_retention(app: State<App>) -> Json<Vec<RetentionPolicy>> {
+    let mut policies: Vec<RetentionPolicy> = app
+        .config
+        .channel_retention
+        .iter()
+        .map(|entry| RetentionPolicy {
+            channel_id: entry.key().clone(),
+            days: *entry.value(),
+        })
+        .collect();
+    policies.sort_by(|a, b| a.channel_id.cmp(&b.channel_id));
+
+    Json(policies)
+}
+
+pub async fn set_retention(
+    app: State<App>,
+    Json(RetentionRequest { channels, days }): Json<RetentionRequest>,
+) -> Result<(), Error> {
+    if days == 0 {
+        return Err(Error::InvalidParam(
+            "Retention needs to be at least one day".to_owned(),
+        ));
+    }
+
+    for channel_id in channels {
+        app.config.channel_retention.insert(channel_id, days);
+    }
+    app.config.save()?;
+
+    Ok(())
+}
+
+pub async fn remove_retention(
+    app: State<App>,
+    Json(ChannelsRequest { channels }): Json<ChannelsRequest>,
+) -> Result<(), Error> {
+    for channel_id in channels {
+        app.config.channel_retention.remove(&channel_id);
+    }
+    app.config.save()?;
+
+    Ok(())
+}
@@ -1,34 +1,50 @@
 use super::{
-    responders::logs::LogsResponse,
+    responders::logs::{LiveLogsResponse, LogsResponse},
     schema::{
-        AvailableLogs, AvailableLogsParams, Channel, ChannelIdType, ChannelLogsByDatePath,
-        ChannelLogsStats, ChannelParam, ChannelsList, LogsParams, LogsPathChannel, SearchParams,
+        AvailableLogDate, AvailableLogs, AvailableLogsParams, Channel, ChannelActivityBucket,
+        ChannelIdType, ChannelLogsByDatePath, ChannelLogsStats, ChannelParam, ChannelsList,
+        EmoteStatsParams, EmoteUsage, LeaderboardEntry, LeaderboardParams, LogsParams,
+        LogsPathChannel, MessageContextParams, MessagePath, SearchParams, TimeseriesParams,
         UserIdType, UserLogPathParams, UserLogsDatePath, UserLogsStats, UserNameHistoryParam,
-        UserParam,
+        UserParam, WordStatsParams, WordUsage,
     },
 };
 use crate::{
     app::App,
+    archive::ColdArchive,
     db::{
         self, read_available_channel_logs, read_available_user_logs, read_channel,
-        read_random_channel_line, read_random_user_line, read_user,
+        read_random_channel_line, read_random_user_line, read_user, schema::MessageType,
     },
     error::Error,
-    logs::{schema::LogRangeParams, stream::LogsStream},
+    logs::{
+        deletions::MessageDeletions, live::LiveLogsStream, modlog::UserModLog,
+        schema::LogRangeParams, search::SearchQuery, stream::LogsStream,
+    },
     web::schema::LogsPathDate,
-    Result,
+    Result, ShutdownRx,
 };
 use aide::axum::IntoApiResponse;
 use axum::{
     extract::{Path, Query, RawQuery, State},
     response::{IntoResponse, Redirect, Response},
-    Json,
+    Extension, Json,
 };
 use axum_extra::{headers::CacheControl, TypedHeader};
-use chrono::{DateTime, Days, Months, NaiveDate, NaiveTime, Utc};
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveTime, Utc};
+use futures::TryStreamExt;
 use rand::{distr::Alphanumeric, rng, Rng};
-use std::time::Duration;
+use std::{cmp::Reverse, str::FromStr, time::Duration};
 use tracing::debug;
+use uuid::Uuid;
+
+const MAX_MESSAGE_CONTEXT: u64 = 1000;
+const MAX_TIMESERIES_BUCKETS: i64 = 10_000;
+const DEFAULT_LEADERBOARD_PAGE_SIZE: u64 = 10;
+const MAX_LEADERBOARD_PAGE_SIZE: u64 = 100;
+const DEFAULT_STATS_LIMIT: u64 = 20;
+const MAX_STATS_LIMIT: u64 = 1000;
+const MAX_NGRAM: u8 = 3;
 
 pub async fn get_channels(app: State<App>) -> impl IntoApiResponse {
     let channel_ids = app.config.channels.read().unwrap().clone();
@@ -112,6 +128,215 @@ pub async fn get_channel_stats(
     }))
 }
 
+pub async fn get_channel_leaderboard(
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(params): Query<LeaderboardParams>,
+    app: State<App>,
+) -> Result<Json<Vec<LeaderboardEntry>>> {
+    let channel_id = match channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
+        ChannelIdType::Id => channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let page_size = params.page_size.unwrap_or(DEFAULT_LEADERBOARD_PAGE_SIZE);
+    if page_size == 0 || page_size > MAX_LEADERBOARD_PAGE_SIZE {
+        return Err(Error::InvalidParam(format!(
+            "The page size must be between 1 and {MAX_LEADERBOARD_PAGE_SIZE}"
+        )));
+    }
+    let offset = params.page.saturating_mul(page_size);
+
+    let message_type = params
+        .message_type
+        .map(|value| {
+            MessageType::from_str(&value.to_uppercase())
+                .map_err(|_| Error::InvalidParam(format!("Unknown message type `{value}`")))
+        })
+        .transpose()?;
+
+    let excluded_logins: &[String] = if params.exclude_bots {
+        &app.config.known_bots
+    } else {
+        &[]
+    };
+
+    let rows = db::get_channel_leaderboard(
+        &app.db,
+        &channel_id,
+        params.rank_by,
+        message_type,
+        excluded_logins,
+        range_params,
+        (page_size, offset),
+    )
+    .await?;
+
+    let user_ids = rows.iter().map(|(user_id, _)| user_id.clone()).collect();
+    let mut users = app.get_users(user_ids, vec![], false).await?;
+
+    let entries = rows
+        .into_iter()
+        .zip(offset + 1..)
+        .map(|((user_id, score), rank)| LeaderboardEntry {
+            rank,
+            user_login: users.remove(&user_id),
+            user_id,
+            score,
+        })
+        .collect();
+
+    Ok(Json(entries))
+}
+
+pub async fn get_channel_emote_stats(
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(params): Query<EmoteStatsParams>,
+    app: State<App>,
+) -> Result<Json<Vec<EmoteUsage>>> {
+    let channel_id = match channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
+        ChannelIdType::Id => channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let limit = stats_limit(params.limit)?;
+    let emotes = db::get_emote_usage(&app.db, &channel_id, None, range_params, limit).await?;
+
+    Ok(Json(emotes))
+}
+
+pub async fn get_user_emote_stats(
+    Path(user_params): Path<UserLogPathParams>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(params): Query<EmoteStatsParams>,
+    app: State<App>,
+) -> Result<Json<Vec<EmoteUsage>>> {
+    let (channel_id, user_id) = resolve_user_params(&user_params, &app).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let limit = stats_limit(params.limit)?;
+    let emotes =
+        db::get_emote_usage(&app.db, &channel_id, Some(&user_id), range_params, limit).await?;
+
+    Ok(Json(emotes))
+}
+
+pub async fn get_channel_word_stats(
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(params): Query<WordStatsParams>,
+    app: State<App>,
+) -> Result<Json<Vec<WordUsage>>> {
+    let channel_id = match channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
+        ChannelIdType::Id => channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let words = read_word_stats(&app, &channel_id, None, range_params, &params).await?;
+
+    Ok(Json(words))
+}
+
+pub async fn get_user_word_stats(
+    Path(user_params): Path<UserLogPathParams>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(params): Query<WordStatsParams>,
+    app: State<App>,
+) -> Result<Json<Vec<WordUsage>>> {
+    let (channel_id, user_id) = resolve_user_params(&user_params, &app).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let words = read_word_stats(&app, &channel_id, Some(&user_id), range_params, &params).await?;
+
+    Ok(Json(words))
+}
+
+async fn read_word_stats(
+    app: &App,
+    channel_id: &str,
+    user_id: Option<&str>,
+    range_params: LogRangeParams,
+    params: &WordStatsParams,
+) -> Result<Vec<WordUsage>> {
+    let limit = stats_limit(params.limit)?;
+    let ngram = params.ngram.unwrap_or(1);
+    if !(1..=MAX_NGRAM).contains(&ngram) {
+        return Err(Error::InvalidParam(format!(
+            "The ngram size must be between 1 and {MAX_NGRAM}"
+        )));
+    }
+
+    db::get_word_usage(
+        &app.db,
+        channel_id,
+        user_id,
+        &app.config.stopwords,
+        range_params,
+        (ngram, limit),
+    )
+    .await
+}
+
+fn stats_limit(limit: Option<u64>) -> Result<u64> {
+    let limit = limit.unwrap_or(DEFAULT_STATS_LIMIT);
+    if limit == 0 || limit > MAX_STATS_LIMIT {
+        return Err(Error::InvalidParam(format!(
+            "The limit must be between 1 and {MAX_STATS_LIMIT}"
+        )));
+    }
+    Ok(limit)
+}
+
+pub async fn get_channel_activity(
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(TimeseriesParams { interval }): Query<TimeseriesParams>,
+    app: State<App>,
+) -> Result<Json<Vec<ChannelActivityBucket>>> {
+    let channel_id = match channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
+        ChannelIdType::Id => channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let (from, to) = range_params.range().ok_or_else(|| {
+        Error::InvalidParam("The `from` and `to` query params are required".to_owned())
+    })?;
+
+    let bucket_count = (to - from).num_seconds() / interval.duration().num_seconds();
+    if bucket_count > MAX_TIMESERIES_BUCKETS {
+        return Err(Error::InvalidParam(format!(
+            "The range contains more than {MAX_TIMESERIES_BUCKETS} buckets, use a larger interval"
+        )));
+    }
+
+    let buckets = db::get_channel_activity(&app.db, &channel_id, interval, (from, to)).await?;
+
+    Ok(Json(buckets))
+}
+
 pub async fn get_user_stats(
     Path(user_params): Path<UserLogPathParams>,
     Query(range_params): Query<LogRangeParams>,
@@ -131,6 +356,20 @@ pub async fn get_user_stats(
     Ok(Json(stats))
 }
 
+pub async fn get_user_modlog(
+    Path(user_params): Path<UserLogPathParams>,
+    Query(range_params): Query<LogRangeParams>,
+    app: State<App>,
+) -> Result<Json<UserModLog>> {
+    let (channel_id, user_id) = resolve_user_params(&user_params, &app).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let messages = db::read_user_moderation(&app.db, &channel_id, &user_id, range_params).await?;
+
+    Ok(Json(UserModLog::from_messages(&messages)))
+}
+
 pub async fn get_channel_logs_by_date(
     app: State<App>,
     Path(channel_log_params): Path<ChannelLogsByDatePath>,
@@ -166,12 +405,40 @@ async fn get_channel_logs_inner(
     range: (DateTime<Utc>, DateTime<Utc>),
 ) -> Result<impl IntoApiResponse> {
     app.check_opted_out(channel_id, None)?;
+    params.validate()?;
 
-    let stream = read_channel(&app.db, channel_id, params, &app.flush_buffer, range).await?;
+    let stream = match app.cold_archive.as_deref() {
+        Some(archive) if archive.covers(&app.db, range).await? => {
+            debug!("Reading channel {channel_id} from the archive");
+            let messages = archive
+                .read_channel(
+                    channel_id,
+                    params.with_lookahead(),
+                    db::narrow_range(&params, range),
+                    &app.config.opt_out,
+                )
+                .await?;
+            LogsStream::new_provided(messages)?
+        }
+        _ => {
+            read_channel(
+                &app.db,
+                channel_id,
+                params.with_lookahead(),
+                &app.flush_buffer,
+                range,
+            )
+            .await?
+        }
+    };
+    let (stream, next_cursor) = stream.into_page(params.limit).await?;
+    let deletions = load_deletions(app, channel_id, &params, range).await?;
 
     let logs = LogsResponse {
         response_type: params.response_type(),
         stream,
+        next_cursor,
+        deletions,
     };
 
     let cache = if Utc::now() < range.1 {
@@ -198,7 +465,7 @@ pub async fn get_user_logs(
         let logs = get_user_logs_inner(&app, &channel_id, &user_id, logs_params, range).await?;
         Ok(logs.into_response())
     } else {
-        let available_logs = read_available_user_logs(&app.db, &channel_id, &user_id).await?;
+        let available_logs = read_available_logs(&app, &channel_id, Some(&user_id)).await?;
         let latest_log = available_logs.first().ok_or(Error::NotFound)?;
 
         let UserLogPathParams {
@@ -249,19 +516,43 @@ async fn get_user_logs_inner(
     logs_params: LogsParams,
     range: (DateTime<Utc>, DateTime<Utc>),
 ) -> Result<impl IntoApiResponse> {
-    let stream = read_user(
-        &app.db,
-        channel_id,
-        user_id,
-        logs_params,
-        &app.flush_buffer,
-        range,
-    )
-    .await?;
+    logs_params.validate()?;
+
+    let stream = match app.cold_archive.as_deref() {
+        Some(archive) if archive.covers(&app.db, range).await? => {
+            debug!("Reading user {user_id} in channel {channel_id} from the archive");
+            let messages = archive
+                .read_user(
+                    &app.db,
+                    channel_id,
+                    user_id,
+                    logs_params.with_lookahead(),
+                    db::narrow_range(&logs_params, range),
+                    |_| true,
+                )
+                .await?;
+            LogsStream::new_provided(messages)?
+        }
+        _ => {
+            read_user(
+                &app.db,
+                channel_id,
+                user_id,
+                logs_params.with_lookahead(),
+                &app.flush_buffer,
+                range,
+            )
+            .await?
+        }
+    };
+    let (stream, next_cursor) = stream.into_page(logs_params.limit).await?;
+    let deletions = load_deletions(app, channel_id, &logs_params, range).await?;
 
     let logs = LogsResponse {
         stream,
         response_type: logs_params.response_type(),
+        next_cursor,
+        deletions,
     };
 
     let cache = if Utc::now() < range.1 {
@@ -288,10 +579,10 @@ pub async fn list_available_logs(
             UserParam::User(name) => app.get_user_id_by_name(&name).await?,
         };
         app.check_opted_out(&channel_id, Some(&user_id))?;
-        read_available_user_logs(&app.db, &channel_id, &user_id).await?
+        read_available_logs(&app, &channel_id, Some(&user_id)).await?
     } else {
         app.check_opted_out(&channel_id, None)?;
-        read_available_channel_logs(&app.db, &channel_id).await?
+        read_available_logs(&app, &channel_id, None).await?
     };
 
     if !available_logs.is_empty() {
@@ -320,6 +611,8 @@ pub async fn random_channel_line(
     let logs = LogsResponse {
         stream,
         response_type: logs_params.response_type(),
+        next_cursor: None,
+        deletions: None,
     };
     Ok((no_cache_header(), logs))
 }
@@ -339,6 +632,8 @@ pub async fn random_user_line(
     let logs = LogsResponse {
         stream,
         response_type: logs_params.response_type(),
+        next_cursor: None,
+        deletions: None,
     };
     Ok((no_cache_header(), logs))
 }
@@ -353,20 +648,332 @@ pub async fn search_user_logs(
 
     app.check_opted_out(&channel_id, Some(&user_id))?;
 
-    let stream = db::search_user_logs(
+    logs_params.validate()?;
+    let search = SearchQuery::parse(&search_params.q)?;
+    let range = read_user_log_range(&app, &channel_id, &user_id).await?;
+
+    let stream = match app.cold_archive.as_deref() {
+        Some(archive) => {
+            search_with_archive(
+                &app,
+                archive,
+                &channel_id,
+                &user_id,
+                &search,
+                logs_params,
+                range,
+            )
+            .await?
+        }
+        None => {
+            db::search_user_logs(
+                &app.db,
+                &channel_id,
+                &user_id,
+                &search,
+                logs_params.with_lookahead(),
+                range,
+            )
+            .await?
+        }
+    };
+    let (stream, next_cursor) = stream.into_page(logs_params.limit).await?;
+    let deletions = load_deletions(&app, &channel_id, &logs_params, range).await?;
+
+    let logs = LogsResponse {
+        stream,
+        response_type: logs_params.response_type(),
+        next_cursor,
+        deletions,
+    };
+    Ok(logs)
+}
+
+/// Searches the archived and the stored messages of the user.
+/// When the archive has matches, both are read up to the end of the page and merged in memory
+async fn search_with_archive(
+    app: &App,
+    archive: &ColdArchive,
+    channel_id: &str,
+    user_id: &str,
+    search: &SearchQuery,
+    logs_params: LogsParams,
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Result<LogsStream> {
+    let lookahead_params = logs_params.with_lookahead();
+    let offset = logs_params.offset.unwrap_or(0);
+    let merge_params = LogsParams {
+        offset: None,
+        limit: lookahead_params.limit.map(|limit| limit + offset),
+        ..lookahead_params
+    };
+
+    let mut messages = archive
+        .read_user(
+            &app.db,
+            channel_id,
+            user_id,
+            merge_params,
+            db::narrow_range(&logs_params, range),
+            |msg| search.matches(msg),
+        )
+        .await?;
+    if messages.is_empty() {
+        return db::search_user_logs(
+            &app.db,
+            channel_id,
+            user_id,
+            search,
+            lookahead_params,
+            range,
+        )
+        .await;
+    }
+
+    match db::search_user_logs(&app.db, channel_id, user_id, search, merge_params, range).await {
+        Ok(stream) => messages.extend(stream.try_concat().await?),
+        Err(Error::NotFound) => (),
+        Err(err) => return Err(err),
+    }
+
+    messages.sort_by_key(|msg| (msg.timestamp, msg.uuid()));
+    if logs_params.reverse {
+        messages.reverse();
+    }
+    messages.drain(..(offset as usize).min(messages.len()));
+    if let Some(limit) = lookahead_params.limit {
+        messages.truncate(limit as usize);
+    }
+
+    LogsStream::new_provided(messages)
+}
+
+/// Time range spanning all of the user's messages in the channel, including the archived ones
+async fn read_user_log_range(
+    app: &App,
+    channel_id: &str,
+    user_id: &str,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let stored_range = match db::read_user_log_range(&app.db, channel_id, user_id).await {
+        Ok(range) => Some(range),
+        Err(Error::NotFound) => None,
+        Err(err) => return Err(err),
+    };
+    let archived_days = match app.cold_archive.as_deref() {
+        Some(archive) => archive.user_days(&app.db, channel_id, user_id).await?,
+        None => Vec::new(),
+    };
+    let archived_range = archived_days
+        .first()
+        .zip(archived_days.last())
+        .map(|(first, last)| (*first, *last + Days::new(1)));
+
+    match (stored_range, archived_range) {
+        (Some((from, to)), Some((archived_from, archived_to))) => {
+            Ok((from.min(archived_from), to.max(archived_to)))
+        }
+        (Some(range), None) | (None, Some(range)) => Ok(range),
+        (None, None) => Err(Error::NotFound),
+    }
+}
+
+/// Dates with logs in the database or the archive, newest first.
+/// User logs are listed by month, channel logs by day
+async fn read_available_logs(
+    app: &App,
+    channel_id: &str,
+    user_id: Option<&str>,
+) -> Result<Vec<AvailableLogDate>> {
+    let mut available_logs = match user_id {
+        Some(user_id) => read_available_user_logs(&app.db, channel_id, user_id).await?,
+        None => read_available_channel_logs(&app.db, channel_id).await?,
+    };
+
+    if let Some(archive) = app.cold_archive.as_deref() {
+        let archived_days = match user_id {
+            Some(user_id) => archive.user_days(&app.db, channel_id, user_id).await?,
+            None => archive.channel_days(channel_id).await?,
+        };
+        available_logs.extend(archived_days.into_iter().map(|day| AvailableLogDate {
+            year: day.year().to_string(),
+            month: day.month().to_string(),
+            day: user_id.is_none().then(|| day.day().to_string()),
+        }));
+
+        // Messages can be imported into archived months again
+        available_logs.sort_by_key(|date| {
+            Reverse((
+                date.year.parse::<u32>().unwrap_or_default(),
+                date.month.parse::<u32>().unwrap_or_default(),
+                date.day
+                    .as_deref()
+                    .map(|day| day.parse::<u32>().unwrap_or_default()),
+            ))
+        });
+        available_logs.dedup_by(|a, b| a.year == b.year && a.month == b.month && a.day == b.day);
+    }
+
+    Ok(available_logs)
+}
+
+pub async fn get_channel_live_logs(
+    app: State<App>,
+    Extension(shutdown_rx): Extension<ShutdownRx>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(logs_params): Query<LogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = match channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
+        ChannelIdType::Id => channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let stream = LiveLogsStream::new(app.0, channel_id, None, shutdown_rx);
+
+    let logs = LiveLogsResponse {
+        stream,
+        response_type: logs_params.response_type(),
+    };
+    Ok((no_cache_header(), logs))
+}
+
+pub async fn get_user_live_logs(
+    app: State<App>,
+    Extension(shutdown_rx): Extension<ShutdownRx>,
+    Path(user_params): Path<UserLogPathParams>,
+    Query(logs_params): Query<LogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let (channel_id, user_id) = resolve_user_params(&user_params, &app).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let stream = LiveLogsStream::new(app.0, channel_id, Some(user_id), shutdown_rx);
+
+    let logs = LiveLogsResponse {
+        stream,
+        response_type: logs_params.response_type(),
+    };
+    Ok((no_cache_header(), logs))
+}
+
+pub async fn get_message(
+    app: State<App>,
+    Path(MessagePath { channel_info, id }): Path<MessagePath>,
+    Query(context_params): Query<MessageContextParams>,
+    Query(logs_params): Query<LogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = match channel_info.channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel_info.channel).await?,
+        ChannelIdType::Id => channel_info.channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let id =
+        Uuid::try_parse(&id).map_err(|_| Error::InvalidParam("Invalid message id".to_owned()))?;
+
+    let MessageContextParams {
+        before,
+        after,
+        timestamp,
+    } = context_params;
+    if before > MAX_MESSAGE_CONTEXT || after > MAX_MESSAGE_CONTEXT {
+        return Err(Error::InvalidParam(format!(
+            "At most {MAX_MESSAGE_CONTEXT} messages of context can be requested"
+        )));
+    }
+
+    let messages = db::read_message_with_context(
+        &app.db,
+        &channel_id,
+        id,
+        timestamp,
+        (before, after),
+        &app.flush_buffer,
+    )
+    .await?;
+
+    let message = messages
+        .iter()
+        .find(|msg| msg.uuid() == id)
+        .ok_or(Error::NotFound)?;
+    app.check_opted_out(&channel_id, Some(&message.user_id))?;
+
+    let range = messages
+        .first()
+        .zip(messages.last())
+        .and_then(|(first, last)| {
+            Some((
+                DateTime::from_timestamp_millis(first.timestamp as i64)?,
+                DateTime::from_timestamp_millis(last.timestamp as i64 + 1)?,
+            ))
+        })
+        .ok_or(Error::NotFound)?;
+    let deletions = load_deletions(&app, &channel_id, &logs_params, range).await?;
+
+    let logs = LogsResponse {
+        stream: LogsStream::new_provided(messages)?,
+        response_type: logs_params.response_type(),
+        next_cursor: None,
+        deletions,
+    };
+    Ok((no_cache_header(), logs))
+}
+
+pub async fn search_channel_logs(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(search_params): Query<SearchParams>,
+    Query(range_params): Query<LogRangeParams>,
+    Query(logs_params): Query<LogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = match channel_id_type {
+        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
+        ChannelIdType::Id => channel,
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let range = range_params.range().ok_or_else(|| {
+        Error::InvalidParam("The `from` and `to` query params are required".to_owned())
+    })?;
+
+    logs_params.validate()?;
+    let search = SearchQuery::parse(&search_params.q)?;
+
+    let stream = db::search_channel_logs(
         &app.db,
         &channel_id,
-        &user_id,
-        &search_params.q,
-        logs_params,
+        &search,
+        logs_params.with_lookahead(),
+        &app.flush_buffer,
+        range,
     )
     .await?;
+    let (stream, next_cursor) = stream.into_page(logs_params.limit).await?;
+    let deletions = load_deletions(&app, &channel_id, &logs_params, range).await?;
 
     let logs = LogsResponse {
         stream,
         response_type: logs_params.response_type(),
+        next_cursor,
+        deletions,
     };
-    Ok(logs)
+
+    let cache = if Utc::now() < range.1 {
+        no_cache_header()
+    } else {
+        cache_header(36000)
+    };
+
+    Ok((cache, logs))
 }
 
 pub async fn get_user_name_history(
@@ -423,3 +1030,19 @@ async fn resolve_user_params(params: &UserLogPathParams, app: &App) -> Result<(S
     };
     Ok((channel_id, user_id))
 }
+
+/// Loads the message deletions when they were requested with `markDeleted`
+async fn load_deletions(
+    app: &App,
+    channel_id: &str,
+    params: &LogsParams,
+    range: (DateTime<Utc>, DateTime<Utc>),
+) -> Result<Option<MessageDeletions>> {
+    if !params.mark_deleted {
+        return Ok(None);
+    }
+
+    let deletions =
+        db::read_message_deletions(&app.db, channel_id, range, &app.flush_buffer).await?;
+    Ok(Some(deletions))
+}
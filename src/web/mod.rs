@@ -35,7 +35,19 @@ use tower_http::{
 };
 use tracing::{debug, info};
 
-const CAPABILITIES: &[&str] = &["arbitrary-range-query", "search", "stats", "namehistory"];
+const CAPABILITIES: &[&str] = &[
+    "arbitrary-range-query",
+    "search",
+    "stats",
+    "namehistory",
+    "live",
+    "message",
+    "modlog",
+    "timeseries",
+    "leaderboard",
+    "emotes",
+    "words",
+];
 
 pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessage>) {
     aide::generate::on_error(|error| {
@@ -65,6 +77,56 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.tag("Admin").description("Leave the specified channels")
             }),
         )
+        .api_route(
+            "/purges",
+            get_with(admin::list_purges, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin")
+                    .description("List the deletion jobs of opted out users and their progress")
+            }),
+        )
+        .api_route(
+            "/exports",
+            post_with(admin::start_export, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description(
+                    "Export a channel's logs into a justlog directory tree in the configured archive folder",
+                )
+            })
+            .get_with(admin::list_exports, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin")
+                    .description("List the channel archive exports and their progress")
+            }),
+        )
+        .api_route(
+            "/exports/parquet",
+            get_with(admin::export_parquet, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description(
+                    "Download a channel's messages as Parquet, optionally limited to a range",
+                )
+            }),
+        )
+        .api_route(
+            "/retention",
+            get_with(admin::list_retention, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin")
+                    .description("List the channels with a retention period")
+            })
+            .put_with(admin::set_retention, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description(
+                    "Set how many days of logs are kept for the specified channels, older logs are deleted periodically",
+                )
+            })
+            .delete_with(admin::remove_retention, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin")
+                    .description("Keep the logs of the specified channels forever")
+            }),
+        )
         .route_layer(middleware::from_fn_with_state(app.clone(), admin_auth))
         .layer(Extension(bot_tx));
 
@@ -95,18 +157,84 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.description("Search user logs using the provided query")
             }),
         )
+        .api_route(
+            "/{channel_id_type}/{channel}/search",
+            get_with(handlers::search_channel_logs, |op| {
+                op.description("Search channel logs in the given time range using the provided query")
+            }),
+        )
         .api_route(
             "/{channel_id_type}/{channel}/{user_id_type}/{user}/stats",
             get_with(handlers::get_user_stats, |op| {
                 op.description("Get user stats")
             }),
         )
+        .api_route(
+            "/{channel_id_type}/{channel}/{user_id_type}/{user}/stats/emotes",
+            get_with(handlers::get_user_emote_stats, |op| {
+                op.description("Get the emotes most used by a user in a channel")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/{user_id_type}/{user}/stats/words",
+            get_with(handlers::get_user_word_stats, |op| {
+                op.description("Get the words or phrases most used by a user in a channel")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/{user_id_type}/{user}/modlog",
+            get_with(handlers::get_user_modlog, |op| {
+                op.description("Get the bans, timeouts and deleted messages of a user in a channel")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/stats/timeseries",
+            get_with(handlers::get_channel_activity, |op| {
+                op.description("Get channel activity in the given time range, bucketed by the given interval")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/stats/emotes",
+            get_with(handlers::get_channel_emote_stats, |op| {
+                op.description("Get the emotes most used in a channel")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/stats/words",
+            get_with(handlers::get_channel_word_stats, |op| {
+                op.description("Get the words or phrases most used in a channel")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/leaderboard",
+            get_with(handlers::get_channel_leaderboard, |op| {
+                op.description("Get a page of the channel's top chatters")
+            }),
+        )
         .api_route(
             "/{channel_id_type}/{channel}/stats",
             get_with(handlers::get_channel_stats, |op| {
                 op.description("Get channel stats")
             }),
         )
+        .api_route(
+            "/{channel_id_type}/{channel}/message/{id}",
+            get_with(handlers::get_message, |op| {
+                op.description("Get a message by its id, along with the given amount of channel messages before and after it")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/live",
+            get_with(handlers::get_channel_live_logs, |op| {
+                op.description("Follow channel logs in real time as a stream of server-sent events")
+            }),
+        )
+        .api_route(
+            "/{channel_id_type}/{channel}/{user_id_type}/{user}/live",
+            get_with(handlers::get_user_live_logs, |op| {
+                op.description("Follow user logs in a channel in real time as a stream of server-sent events")
+            }),
+        )
         .api_route(
             "/{channel_id_type}/{channel}/random",
             get_with(handlers::random_channel_line, |op| {
@@ -163,6 +291,7 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
         .route("/metrics", get(metrics))
         .finish_api(&mut api)
         .layer(Extension(Arc::new(api)))
+        .layer(Extension(shutdown_rx.clone()))
         .with_state(app)
         .layer(cors)
         .layer(CompressionLayer::new().quality(CompressionLevel::Fastest));
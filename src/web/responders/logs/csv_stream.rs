@@ -0,0 +1,140 @@
+use crate::{db::schema::StructuredMessage, logs::stream::LogsStream, Result};
+use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::pin;
+
+const CHUNK_SIZE: usize = 3000;
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+const HEADER: &str =
+    "timestamp,channel,user_id,user_login,display_name,type,text,badges,message_id\r\n";
+
+pub struct CsvLogsStream {
+    inner: TryChunks<LogsStream>,
+    header_written: bool,
+}
+
+impl CsvLogsStream {
+    pub fn new(stream: LogsStream) -> Self {
+        let inner = stream.try_chunks(CHUNK_SIZE);
+        Self {
+            inner,
+            header_written: false,
+        }
+    }
+}
+
+impl Stream for CsvLogsStream {
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = {
+            let fut = self.inner.next();
+            pin!(fut);
+            fut.poll(cx)
+        };
+        let header_written = self.header_written;
+
+        poll.map(|item| {
+            let Some(result) = item else {
+                // An empty export still gets its header row
+                return (!header_written).then(|| {
+                    self.header_written = true;
+                    Ok(HEADER.to_owned())
+                });
+            };
+            self.header_written = true;
+
+            Some(match result {
+                Ok(chunk) => {
+                    let mut output = String::with_capacity(chunk.len() * 64);
+                    if !header_written {
+                        output.push_str(HEADER);
+                    }
+
+                    for msg in chunk.into_iter().flatten() {
+                        write_csv_row(&mut output, &msg);
+                        output.push_str("\r\n");
+                    }
+
+                    Ok(output)
+                }
+                Err(err) => Err(err.1),
+            })
+        })
+    }
+}
+
+pub fn write_csv_row(output: &mut String, msg: &StructuredMessage) {
+    let timestamp = chrono::DateTime::from_timestamp_millis(msg.timestamp as i64)
+        .unwrap_or_default()
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+
+    let fields = [
+        timestamp.as_str(),
+        &msg.channel_login,
+        &msg.user_id,
+        &msg.user_login,
+        msg.display_name(),
+        &msg.message_type.to_string(),
+        &msg.user_friendly_text(),
+        &msg.badges.join(","),
+        &msg.id().unwrap_or_default(),
+    ];
+
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        write_field(output, field);
+    }
+}
+
+/// Quotes the field when needed, and neutralizes values that spreadsheets would evaluate as formulas
+fn write_field(output: &mut String, field: &str) {
+    let is_formula = field.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    let needs_quotes = is_formula || field.contains([',', '"', '\r', '\n']);
+
+    if needs_quotes {
+        output.push('"');
+    }
+    if is_formula {
+        output.push('\'');
+    }
+    for c in field.chars() {
+        if c == '"' {
+            output.push('"');
+        }
+        output.push(c);
+    }
+    if needs_quotes {
+        output.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_field;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn escape_fields() {
+        let cases = [
+            ("forsenE", "forsenE"),
+            ("a, b", "\"a, b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("line\nbreak", "\"line\nbreak\""),
+            ("=1+1", "\"'=1+1\""),
+            ("", ""),
+        ];
+
+        for (input, expected) in cases {
+            let mut output = String::new();
+            write_field(&mut output, input);
+            assert_eq!(expected, output);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{
+    db::schema::{MessageType, StructuredMessage},
+    logs::stream::LogsStream,
+    Result,
+};
+use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
+use std::{
+    fmt::Write,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::pin;
+
+const CHUNK_SIZE: usize = 3000;
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+const STYLE: &str = "
+body { margin: 0; padding: 1em; background: #18181b; color: #efeff1; font: 14px/1.6 sans-serif; }
+.msg { padding: 1px 0; overflow-wrap: anywhere; }
+.system { color: #adadb8; font-style: italic; }
+time { color: #adadb8; font-family: monospace; margin-right: .5em; }
+.badge { display: inline-block; margin-right: .3em; padding: 0 .3em; border-radius: 3px; background: #3a3a3d; font-size: .8em; }
+.name { font-weight: bold; }
+.emote { padding: 0 .2em; border-radius: 3px; background: #2f2f35; color: #bf94ff; }
+";
+const FOOTER: &str = "</main>\n</body>\n</html>\n";
+
+/// Self-contained HTML transcript, emotes are rendered as text placeholders
+pub struct HtmlLogsStream {
+    inner: TryChunks<LogsStream>,
+    is_start: bool,
+    is_end: bool,
+}
+
+impl HtmlLogsStream {
+    pub fn new(stream: LogsStream) -> Self {
+        let inner = stream.try_chunks(CHUNK_SIZE);
+        Self {
+            inner,
+            is_start: true,
+            is_end: false,
+        }
+    }
+}
+
+impl Stream for HtmlLogsStream {
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.is_end {
+            return Poll::Ready(None);
+        }
+
+        let fut = self.inner.next();
+        pin!(fut);
+
+        match fut.poll(cx) {
+            Poll::Ready(Some(result)) => match result {
+                Ok(chunks) => {
+                    let mut output = String::with_capacity(chunks.len() * 256);
+
+                    for msg in chunks.into_iter().flatten() {
+                        if self.is_start {
+                            write_header(&mut output, &msg.channel_login);
+                            self.is_start = false;
+                        }
+                        write_html_line(&mut output, &msg);
+                        output.push('\n');
+                    }
+
+                    Poll::Ready(Some(Ok(output)))
+                }
+                Err(err) => Poll::Ready(Some(Err(err.1))),
+            },
+            Poll::Ready(None) => {
+                self.is_end = true;
+                // No lines were retrieved
+                if self.is_start {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Ok(FOOTER.to_owned())))
+                }
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn write_header(output: &mut String, channel: &str) {
+    let _ = write!(
+        output,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>#{channel} logs</title>\n<style>{STYLE}</style>\n</head>\n<body>\n<main>\n",
+        channel = Escaped(channel),
+    );
+}
+
+pub fn write_html_line(output: &mut String, msg: &StructuredMessage) {
+    let datetime =
+        chrono::DateTime::from_timestamp_millis(msg.timestamp as i64).unwrap_or_default();
+    let is_chat = msg.message_type == MessageType::PrivMsg && !msg.user_login.is_empty();
+
+    let _ = write!(
+        output,
+        "<div class=\"msg{}\"><time datetime=\"{}\">{}</time>",
+        if is_chat { "" } else { " system" },
+        datetime.to_rfc3339(),
+        datetime.format(TIMESTAMP_FORMAT)
+    );
+
+    if is_chat {
+        for badge in msg.badges.iter().filter(|badge| !badge.is_empty()) {
+            let name = badge
+                .split_once('/')
+                .map_or(badge.as_ref(), |(name, _)| name);
+            let _ = write!(
+                output,
+                "<span class=\"badge\" title=\"{}\">{}</span>",
+                Escaped(badge),
+                Escaped(name)
+            );
+        }
+
+        output.push_str("<span class=\"name\"");
+        if let Some(color) = msg.color {
+            let _ = write!(output, " style=\"color:#{color:06x}\"");
+        }
+        let _ = write!(output, ">{}</span>: ", Escaped(msg.display_name()));
+
+        write_text_with_emotes(output, &msg.user_friendly_text(), &msg.emotes);
+    } else {
+        let _ = write!(output, "{}", Escaped(&msg.user_friendly_text()));
+    }
+
+    output.push_str("</div>");
+}
+
+/// Replaces the character ranges from the `emotes` tag (`id:start-end,start-end/id:start-end`) with placeholders
+fn write_text_with_emotes(output: &mut String, text: &str, emotes_tag: &str) {
+    let mut emotes: Vec<(usize, usize, &str)> = emotes_tag
+        .split('/')
+        .filter_map(|emote| emote.split_once(':'))
+        .flat_map(|(id, ranges)| {
+            ranges.split(',').filter_map(move |range| {
+                let (start, end) = range.split_once('-')?;
+                Some((start.parse().ok()?, end.parse().ok()?, id))
+            })
+        })
+        .filter(|(start, end, _)| start <= end)
+        .collect();
+    emotes.sort_unstable_by_key(|(start, _, _)| *start);
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut position = 0;
+
+    for (start, end, id) in emotes {
+        if start < position || end >= chars.len() {
+            continue;
+        }
+
+        let before: String = chars[position..start].iter().collect();
+        let name: String = chars[start..=end].iter().collect();
+        let _ = write!(
+            output,
+            "{}<span class=\"emote\" data-emote-id=\"{}\" title=\"{}\">{}</span>",
+            Escaped(&before),
+            Escaped(id),
+            Escaped(&name),
+            Escaped(&name)
+        );
+        position = end + 1;
+    }
+
+    let rest: String = chars[position..].iter().collect();
+    let _ = write!(output, "{}", Escaped(&rest));
+}
+
+struct Escaped<'a>(&'a str);
+
+impl std::fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&#39;")?,
+                _ => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_text_with_emotes;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn render_emotes() {
+        let mut output = String::new();
+        write_text_with_emotes(
+            &mut output,
+            "Kappa <b> Kappa LUL",
+            "25:0-4,10-14/425618:16-18",
+        );
+
+        assert_eq!(
+            "<span class=\"emote\" data-emote-id=\"25\" title=\"Kappa\">Kappa</span> &lt;b&gt; \
+             <span class=\"emote\" data-emote-id=\"25\" title=\"Kappa\">Kappa</span> \
+             <span class=\"emote\" data-emote-id=\"425618\" title=\"LUL\">LUL</span>",
+            output
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{
     db::schema::StructuredMessage,
     logs::{
+        deletions::MessageDeletions,
         schema::message::{BasicMessage, FullMessage, ResponseMessage},
         stream::LogsStream,
     },
@@ -32,16 +33,22 @@ pub struct JsonLogsStream {
     is_start: bool,
     is_end: bool,
     response_type: JsonResponseType,
+    deletions: Option<MessageDeletions>,
 }
 
 impl JsonLogsStream {
-    pub fn new(stream: LogsStream, response_type: JsonResponseType) -> Self {
+    pub fn new(
+        stream: LogsStream,
+        response_type: JsonResponseType,
+        deletions: Option<MessageDeletions>,
+    ) -> Self {
         let inner = stream.try_chunks(CHUNK_SIZE);
         Self {
             inner,
             is_start: true,
             is_end: false,
             response_type,
+            deletions,
         }
     }
 
@@ -52,7 +59,12 @@ impl JsonLogsStream {
         let mut messages: VecDeque<T> = messages
             .iter()
             .filter_map(|msg| match T::from_structured(msg) {
-                Ok(parsed) => Some(parsed),
+                Ok(mut parsed) => {
+                    if let Some(deletion) = self.deletions.as_ref().and_then(|d| d.get(msg)) {
+                        parsed.mark_deleted(deletion);
+                    }
+                    Some(parsed)
+                }
                 Err(err) => {
                     error!("Could not parse message {msg:?} from DB: {err}");
                     None
@@ -0,0 +1,107 @@
+use super::{
+    csv_stream::write_csv_row, html_stream::write_html_line, text_stream::write_text_line,
+    JsonResponseType, LogsResponseType,
+};
+use crate::logs::{
+    live::{LiveLogsStream, LiveMessage},
+    schema::message::{BasicMessage, FullMessage, ResponseMessage},
+};
+use aide::{openapi::MediaType, OperationOutput};
+use axum::response::{
+    sse::{Event, KeepAlive},
+    IntoResponse, Response, Sse,
+};
+use futures::{future, StreamExt};
+use indexmap::IndexMap;
+use serde::Serialize;
+use std::convert::Infallible;
+use tracing::error;
+
+pub struct LiveLogsResponse {
+    pub stream: LiveLogsStream,
+    pub response_type: LogsResponseType,
+}
+
+impl IntoResponse for LiveLogsResponse {
+    fn into_response(self) -> Response {
+        let response_type = self.response_type;
+
+        let events = self
+            .stream
+            .filter_map(move |msg| future::ready(serialize_message(&msg, &response_type)))
+            .map(|data| Ok::<_, Infallible>(Event::default().data(data)));
+
+        Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response()
+    }
+}
+
+fn serialize_message(msg: &LiveMessage, response_type: &LogsResponseType) -> Option<String> {
+    let data = match response_type {
+        LogsResponseType::Raw => msg.to_raw_irc(),
+        LogsResponseType::Text => {
+            let mut output = String::new();
+            write_text_line(&mut output, msg);
+            output
+        }
+        LogsResponseType::Html => {
+            let mut output = String::new();
+            write_html_line(&mut output, msg);
+            output
+        }
+        LogsResponseType::Csv => {
+            let mut output = String::new();
+            write_csv_row(&mut output, msg);
+            output
+        }
+        LogsResponseType::Json(JsonResponseType::Full) => to_json::<FullMessage>(msg)?,
+        LogsResponseType::Json(JsonResponseType::Basic) | LogsResponseType::NdJson => {
+            to_json::<BasicMessage>(msg)?
+        }
+    };
+
+    // Carriage returns cannot be transmitted in an SSE data field
+    if data.contains('\r') {
+        Some(data.replace('\r', ""))
+    } else {
+        Some(data)
+    }
+}
+
+fn to_json<'a, T: ResponseMessage<'a> + Serialize>(msg: &'a LiveMessage) -> Option<String> {
+    match T::from_structured(msg) {
+        Ok(message) => Some(serde_json::to_string(&message).unwrap()),
+        Err(err) => {
+            error!("Could not parse live message {msg:?}: {err}");
+            None
+        }
+    }
+}
+
+impl OperationOutput for LiveLogsResponse {
+    type Inner = Self;
+
+    fn operation_response(
+        _: &mut aide::generate::GenContext,
+        _: &mut aide::openapi::Operation,
+    ) -> Option<aide::openapi::Response> {
+        let mut content = IndexMap::with_capacity(1);
+        content.insert("text/event-stream".to_owned(), MediaType::default());
+
+        Some(aide::openapi::Response {
+            description: "Server-sent events stream, with one message per event".into(),
+            content,
+            ..Default::default()
+        })
+    }
+
+    fn inferred_responses(
+        ctx: &mut aide::generate::GenContext,
+        operation: &mut aide::openapi::Operation,
+    ) -> Vec<(Option<u16>, aide::openapi::Response)> {
+        let res = Self::operation_response(ctx, operation).unwrap();
+
+        vec![(Some(200), res)]
+    }
+}
@@ -1,13 +1,21 @@
+mod csv_stream;
+mod html_stream;
 mod json_stream;
+mod live;
 mod ndjson_stream;
 mod text_stream;
 
 pub use json_stream::JsonResponseType;
+pub use live::LiveLogsResponse;
 
 use self::{
-    json_stream::JsonLogsStream, ndjson_stream::NdJsonLogsStream, text_stream::TextLogsStream,
+    csv_stream::CsvLogsStream, html_stream::HtmlLogsStream, json_stream::JsonLogsStream,
+    ndjson_stream::NdJsonLogsStream, text_stream::TextLogsStream,
+};
+use crate::logs::{
+    deletions::MessageDeletions, pagination::PageCursor, schema::message::FullMessage,
+    stream::LogsStream,
 };
-use crate::logs::{schema::message::FullMessage, stream::LogsStream};
 use aide::OperationOutput;
 use axum::{
     body::Body,
@@ -17,13 +25,19 @@ use axum::{
 };
 use futures::TryStreamExt;
 use indexmap::IndexMap;
-use mime_guess::mime::{APPLICATION_JSON, TEXT_PLAIN_UTF_8};
+use mime_guess::mime::{APPLICATION_JSON, TEXT_HTML_UTF_8, TEXT_PLAIN_UTF_8};
 use reqwest::header::CONTENT_TYPE;
 use schemars::JsonSchema;
 
+const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
 pub struct LogsResponse {
     pub stream: LogsStream,
     pub response_type: LogsResponseType,
+    /// Sent in the `X-Next-Cursor` header when there are more messages
+    pub next_cursor: Option<PageCursor>,
+    /// Used to mark deleted messages in JSON responses
+    pub deletions: Option<MessageDeletions>,
 }
 
 pub enum LogsResponseType {
@@ -31,6 +45,8 @@ pub enum LogsResponseType {
     Text,
     Json(JsonResponseType),
     NdJson,
+    Csv,
+    Html,
 }
 
 /// Used for schema only, actual serialization is manual
@@ -42,7 +58,7 @@ pub struct JsonLogsResponse<'a> {
 
 impl IntoResponse for LogsResponse {
     fn into_response(self) -> Response {
-        match self.response_type {
+        let mut response = match self.response_type {
             LogsResponseType::Raw => {
                 let stream = self.stream.map_ok(|chunk| {
                     let mut buf = String::new();
@@ -68,7 +84,7 @@ impl IntoResponse for LogsResponse {
                     .into_response()
             }
             LogsResponseType::Json(response_type) => {
-                let stream = JsonLogsStream::new(self.stream, response_type);
+                let stream = JsonLogsStream::new(self.stream, response_type, self.deletions);
                 (
                     set_content_type(&APPLICATION_JSON),
                     Body::from_stream(stream),
@@ -76,14 +92,39 @@ impl IntoResponse for LogsResponse {
                     .into_response()
             }
             LogsResponseType::NdJson => {
-                let stream = NdJsonLogsStream::new(self.stream);
+                let stream = NdJsonLogsStream::new(self.stream, self.deletions);
                 (
                     set_content_type(&"application/x-ndjson"),
                     Body::from_stream(stream),
                 )
                     .into_response()
             }
+            LogsResponseType::Csv => {
+                let stream = CsvLogsStream::new(self.stream);
+                (
+                    set_content_type(&"text/csv; charset=utf-8"),
+                    Body::from_stream(stream),
+                )
+                    .into_response()
+            }
+            LogsResponseType::Html => {
+                let stream = HtmlLogsStream::new(self.stream);
+                (
+                    set_content_type(&TEXT_HTML_UTF_8),
+                    Body::from_stream(stream),
+                )
+                    .into_response()
+            }
+        };
+
+        if let Some(cursor) = self.next_cursor {
+            response.headers_mut().insert(
+                NEXT_CURSOR_HEADER,
+                HeaderValue::from_str(&cursor.to_string()).unwrap(),
+            );
         }
+
+        response
     }
 }
 
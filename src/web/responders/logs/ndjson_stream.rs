@@ -1,5 +1,6 @@
 use crate::{
     logs::{
+        deletions::MessageDeletions,
         schema::message::{BasicMessage, ResponseMessage},
         stream::LogsStream,
     },
@@ -20,12 +21,13 @@ const CHUNK_SIZE: usize = 3000;
 
 pub struct NdJsonLogsStream {
     inner: TryChunks<LogsStream>,
+    deletions: Option<MessageDeletions>,
 }
 
 impl NdJsonLogsStream {
-    pub fn new(stream: LogsStream) -> Self {
+    pub fn new(stream: LogsStream, deletions: Option<MessageDeletions>) -> Self {
         let inner = stream.try_chunks(CHUNK_SIZE);
-        Self { inner }
+        Self { inner, deletions }
     }
 }
 
@@ -43,7 +45,14 @@ impl Stream for NdJsonLogsStream {
                         .iter()
                         .flatten()
                         .filter_map(|msg| match BasicMessage::from_structured(msg) {
-                            Ok(parsed) => Some(parsed),
+                            Ok(mut parsed) => {
+                                if let Some(deletion) =
+                                    self.deletions.as_ref().and_then(|d| d.get(msg))
+                                {
+                                    parsed.mark_deleted(deletion);
+                                }
+                                Some(parsed)
+                            }
                             Err(err) => {
                                 error!("Could not parse message {msg:?} from DB: {err}");
                                 None
@@ -1,4 +1,4 @@
-use crate::{logs::stream::LogsStream, Result};
+use crate::{db::schema::StructuredMessage, logs::stream::LogsStream, Result};
 use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
 use std::{
     fmt::Write,
@@ -34,20 +34,8 @@ impl Stream for TextLogsStream {
                     let mut output = String::with_capacity(chunk.len() * 16);
 
                     for msg in chunk.into_iter().flatten() {
-                        let timestamp =
-                            chrono::DateTime::from_timestamp_millis(msg.timestamp as i64)
-                                .unwrap_or_default()
-                                .format(TIMESTAMP_FORMAT);
-                        let text = msg.user_friendly_text();
-                        let channel = &msg.channel_login;
-                        let username = &msg.user_login;
-
-                        if !username.is_empty() {
-                            let _ =
-                                write!(output, "[{timestamp}] #{channel} {username}: {text}\r\n");
-                        } else {
-                            let _ = write!(output, "[{timestamp}] #{channel} {text}\r\n");
-                        }
+                        write_text_line(&mut output, &msg);
+                        output.push_str("\r\n");
                     }
 
                     Ok(output)
@@ -57,3 +45,18 @@ impl Stream for TextLogsStream {
         })
     }
 }
+
+pub fn write_text_line(output: &mut String, msg: &StructuredMessage) {
+    let timestamp = chrono::DateTime::from_timestamp_millis(msg.timestamp as i64)
+        .unwrap_or_default()
+        .format(TIMESTAMP_FORMAT);
+    let text = msg.user_friendly_text();
+    let channel = &msg.channel_login;
+    let username = &msg.user_login;
+
+    if !username.is_empty() {
+        let _ = write!(output, "[{timestamp}] #{channel} {username}: {text}");
+    } else {
+        let _ = write!(output, "[{timestamp}] #{channel} {text}");
+    }
+}
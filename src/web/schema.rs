@@ -1,10 +1,14 @@
 use super::responders::logs::{JsonResponseType, LogsResponseType};
+use crate::{error::Error, logs::pagination::PageCursor};
 use chrono::{DateTime, Utc};
 use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::Display;
 use strum::Display;
 
+/// Pages are buffered to find the cursor of the next one, so their size is bounded
+const MAX_LOGS_LIMIT: u64 = 10_000;
+
 #[derive(Serialize, JsonSchema)]
 pub struct ChannelsList {
     pub channels: Vec<Channel>,
@@ -77,8 +81,31 @@ pub struct LogsParams {
     pub reverse: bool,
     #[serde(default, deserialize_with = "deserialize_bool_param")]
     pub ndjson: bool,
+    /// CSV with a header row
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub csv: bool,
+    /// Standalone HTML page
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub html: bool,
+    /// At most 10000, use `cursor` to read further pages
     pub limit: Option<u64>,
     pub offset: Option<u64>,
+    /// Resume from the `X-Next-Cursor` header of a previous response, in the direction given by `reverse`.
+    /// Cannot be combined with `offset`
+    #[schemars(with = "Option<String>")]
+    pub cursor: Option<PageCursor>,
+    /// Only return deleted messages, or leave them out
+    pub deleted: Option<DeletedFilter>,
+    /// Include when messages were deleted in JSON responses
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub mark_deleted: bool,
+}
+
+#[derive(Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeletedFilter {
+    Only,
+    Exclude,
 }
 
 impl LogsParams {
@@ -91,10 +118,36 @@ impl LogsParams {
             LogsResponseType::Json(JsonResponseType::Full)
         } else if self.ndjson {
             LogsResponseType::NdJson
+        } else if self.csv {
+            LogsResponseType::Csv
+        } else if self.html {
+            LogsResponseType::Html
         } else {
             LogsResponseType::Text
         }
     }
+
+    /// Requests one more message than the limit, to know whether there is a next page
+    pub fn with_lookahead(self) -> Self {
+        Self {
+            limit: self.limit.map(|limit| limit + 1),
+            ..self
+        }
+    }
+
+    pub fn validate(&self) -> crate::Result<()> {
+        if self.cursor.is_some() && self.offset.is_some() {
+            return Err(Error::InvalidParam(
+                "The `cursor` and `offset` params cannot be used together".to_owned(),
+            ));
+        }
+        if self.limit.is_some_and(|limit| limit > MAX_LOGS_LIMIT) {
+            return Err(Error::InvalidParam(format!(
+                "The limit can be at most {MAX_LOGS_LIMIT}"
+            )));
+        }
+        Ok(())
+    }
 }
 
 fn deserialize_bool_param<'de, D>(deserializer: D) -> Result<bool, D::Error>
@@ -106,6 +159,8 @@ where
 
 #[derive(Deserialize, Debug, JsonSchema)]
 pub struct SearchParams {
+    /// Search query. Supports `"quoted phrases"`, `-excluded` terms, `OR`, `/regex/`
+    /// and the `user:`, `userid:`, `type:`, `badge:` and `flag:` filters
     pub q: String,
 }
 
@@ -157,6 +212,26 @@ pub enum ChannelParam {
     ChannelId(String),
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct MessagePath {
+    #[serde(flatten)]
+    pub channel_info: LogsPathChannel,
+    pub id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MessageContextParams {
+    /// How many channel messages to include before the message
+    #[serde(default)]
+    pub before: u64,
+    /// How many channel messages to include after the message
+    #[serde(default)]
+    pub after: u64,
+    /// RFC 3339 time the message was sent at, within a minute. Speeds up looking up the message,
+    /// which is not found when it was sent at a different time
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
 #[derive(Deserialize, JsonSchema)]
 pub struct UserLogPathParams {
     pub channel_id_type: ChannelIdType,
@@ -172,6 +247,128 @@ pub struct ChannelLogsStats {
     pub top_chatters: Vec<UserLogsStats>,
 }
 
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardParams {
+    /// Defaults to 10, at most 100
+    pub page_size: Option<u64>,
+    /// Starts at 0
+    #[serde(default)]
+    pub page: u64,
+    /// Only count messages of the given type, such as `privmsg`
+    pub message_type: Option<String>,
+    /// Leave out the users listed as known bots
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub exclude_bots: bool,
+    #[serde(default)]
+    pub rank_by: LeaderboardRanking,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum LeaderboardRanking {
+    #[default]
+    Messages,
+    /// Days with at least one message
+    ActiveDays,
+    /// Characters in message texts
+    Characters,
+}
+
+impl LeaderboardRanking {
+    pub fn sql_score(self) -> &'static str {
+        match self {
+            LeaderboardRanking::Messages => "count()",
+            LeaderboardRanking::ActiveDays => "uniqExact(toDate(timestamp))",
+            LeaderboardRanking::Characters => "sum(lengthUTF8(text))",
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LeaderboardEntry {
+    pub rank: u64,
+    pub user_id: String,
+    pub user_login: Option<String>,
+    pub score: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct TimeseriesParams {
+    #[serde(default)]
+    pub interval: TimeseriesInterval,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeseriesInterval {
+    Minute,
+    #[default]
+    Hour,
+    Day,
+}
+
+impl TimeseriesInterval {
+    pub fn sql_interval(self) -> &'static str {
+        match self {
+            TimeseriesInterval::Minute => "INTERVAL 1 MINUTE",
+            TimeseriesInterval::Hour => "INTERVAL 1 HOUR",
+            TimeseriesInterval::Day => "INTERVAL 1 DAY",
+        }
+    }
+
+    pub fn duration(self) -> chrono::Duration {
+        match self {
+            TimeseriesInterval::Minute => chrono::Duration::minutes(1),
+            TimeseriesInterval::Hour => chrono::Duration::hours(1),
+            TimeseriesInterval::Day => chrono::Duration::days(1),
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelActivityBucket {
+    /// Start of the bucket
+    pub timestamp: DateTime<Utc>,
+    pub message_count: u64,
+    pub chatter_count: u64,
+    pub sub_count: u64,
+    pub raid_count: u64,
+    pub ban_count: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct EmoteStatsParams {
+    /// Defaults to 20, at most 1000
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct EmoteUsage {
+    pub emote_id: String,
+    /// As written in the most recent message using the emote
+    pub emote_name: String,
+    pub count: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct WordStatsParams {
+    /// Defaults to 20, at most 1000
+    pub limit: Option<u64>,
+    /// Number of consecutive words to count as a phrase, between 1 (default) and 3
+    pub ngram: Option<u8>,
+}
+
+#[derive(Serialize, JsonSchema, Debug)]
+pub struct WordUsage {
+    /// Lowercased word or phrase
+    pub phrase: String,
+    pub count: u64,
+}
+
 #[derive(Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct UserLogsStats {